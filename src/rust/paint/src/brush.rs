@@ -1,21 +1,91 @@
+use super::pointer_state::PointerSample;
+use super::stroke::Dab;
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+/// How strongly pointer input modulates the brush. Each amount is in `[0, 1]`,
+/// where 0 ignores the input entirely.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Dynamics {
+    pub size_pressure: f32,
+    pub opacity_pressure: f32,
+    pub size_tilt: f32,
+    pub opacity_tilt: f32,
+}
+
 #[wasm_bindgen]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct Brush {
     #[wasm_bindgen(skip)]
     pub color: [f32; 4],
+    #[wasm_bindgen(skip)]
+    pub dynamics: Dynamics,
+    #[wasm_bindgen(skip)]
+    pub spacing: f32,
+    #[wasm_bindgen(skip)]
+    pub size: f32,
+    #[wasm_bindgen(skip)]
+    pub hardness: f32,
+    #[wasm_bindgen(skip)]
+    pub opacity: f32,
 }
 #[wasm_bindgen]
 impl Brush {
     #[wasm_bindgen(constructor)]
     pub fn new(color: &[f32]) -> Result<Brush, JsValue> {
+        let mut brush = Self::default();
+        brush.set_color(color)?;
+        Ok(brush)
+    }
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            color: [0.5, 0.5, 0.5, 1.0],
+            dynamics: Dynamics::default(),
+            spacing: 0.1,
+            size: 16.0,
+            hardness: 0.8,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl Brush {
+    pub fn set_color(&mut self, color: &[f32]) -> Result<(), JsValue> {
         match color.len() {
             4 => {
-                let mut color_arr = [0f32; 4];
-                color_arr.copy_from_slice(color);
-                Ok(Self { color: color_arr })
+                self.color.copy_from_slice(color);
+                Ok(())
             }
             _ => Err("Invalid color length".into()),
         }
     }
+
+    /// The dab this brush leaves at `sample`.
+    pub fn dab(&self, sample: &PointerSample) -> Dab {
+        Dab {
+            x: sample.x,
+            y: sample.y,
+            size: self.size * self.size_factor(sample),
+            opacity: self.opacity * self.opacity_factor(sample),
+        }
+    }
+
+    /// Multiplier applied to the dab size for `sample`.
+    pub fn size_factor(&self, sample: &PointerSample) -> f32 {
+        let pressure = 1.0 - self.dynamics.size_pressure * (1.0 - sample.pressure);
+        let tilt = 1.0 + self.dynamics.size_tilt * sample.tilt();
+        pressure * tilt
+    }
+
+    /// Multiplier applied to the dab opacity for `sample`.
+    pub fn opacity_factor(&self, sample: &PointerSample) -> f32 {
+        let pressure = 1.0 - self.dynamics.opacity_pressure * (1.0 - sample.pressure);
+        let tilt = 1.0 - self.dynamics.opacity_tilt * sample.tilt();
+        pressure * tilt
+    }
 }
@@ -0,0 +1,160 @@
+use wasm_bindgen::prelude::*;
+
+/// A decoded image as straight-alpha RGBA8, top row first.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Decodes PNG or JPEG bytes, detected from the file signature.
+pub fn decode_image(bytes: &[u8]) -> Result<Image, JsValue> {
+    if bytes.starts_with(b"\x89PNG") {
+        decode_png(bytes)
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        decode_jpeg(bytes)
+    } else {
+        Err("Unsupported image format".into())
+    }
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Image, JsValue> {
+    let png_error =
+        |err: png::DecodingError| JsValue::from_str(format!("PNG decode error: {}", err).as_str());
+    let mut decoder = png::Decoder::new(bytes);
+    // palettes, low bit depths and tRNS chunks are expanded to 8-bit gray/RGB(A)
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().map_err(png_error)?;
+    let mut buf = vec![0u8; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(png_error)?;
+
+    // keep the high byte of 16-bit samples
+    if info.bit_depth == png::BitDepth::Sixteen {
+        buf = buf.chunks_exact(2).map(|sample| sample[0]).collect();
+    }
+    let pixels = match info.color_type {
+        png::ColorType::RGBA => buf,
+        png::ColorType::RGB => buf
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|px| [px[0], px[0], px[0], px[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|l| [*l, *l, *l, 255]).collect(),
+        png::ColorType::Indexed => return Err("Unexpanded indexed PNG".into()),
+    };
+    Ok(Image {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+fn decode_jpeg(bytes: &[u8]) -> Result<Image, JsValue> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let buf = decoder
+        .decode()
+        .map_err(|err| JsValue::from_str(format!("JPEG decode error: {}", err).as_str()))?;
+    let info = decoder
+        .info()
+        .ok_or("JPEG decode error: missing frame info")?;
+    let pixels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => buf
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 255])
+            .collect(),
+        jpeg_decoder::PixelFormat::L8 => buf.iter().flat_map(|l| [*l, *l, *l, 255]).collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => buf
+            .chunks_exact(4)
+            .flat_map(|px| {
+                let k = 255 - px[3] as u32;
+                let channel = |c: u8| ((255 - c as u32) * k / 255) as u8;
+                [channel(px[0]), channel(px[1]), channel(px[2]), 255]
+            })
+            .collect(),
+    };
+    Ok(Image {
+        width: info.width as u32,
+        height: info.height as u32,
+        pixels,
+    })
+}
+
+/// Encodes tightly packed 8-bit pixels as PNG. `channels` is 3 for RGB or 4 for RGBA.
+pub fn encode_png(
+    width: u32,
+    height: u32,
+    channels: usize,
+    pixels: &[u8],
+) -> Result<Vec<u8>, JsValue> {
+    let color = match channels {
+        3 => png::ColorType::RGB,
+        4 => png::ColorType::RGBA,
+        _ => return Err("Unsupported channel count".into()),
+    };
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|err| JsValue::from_str(format!("PNG encode error: {}", err).as_str()))?;
+        writer
+            .write_image_data(pixels)
+            .map_err(|err| JsValue::from_str(format!("PNG encode error: {}", err).as_str()))?;
+    }
+    Ok(bytes)
+}
+
+/// Reverses the row order of RGBA8 pixels, converting between GL's bottom-up
+/// layout and the top-down layout image formats use.
+pub fn flip_rows(pixels: &mut [u8], width: usize, height: usize) {
+    let stride = width * 4;
+    for y in 0..height / 2 {
+        let (top, bottom) = pixels.split_at_mut((height - 1 - y) * stride);
+        top[y * stride..(y + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+    }
+}
+
+/// Converts premultiplied RGBA8 to straight alpha in place.
+pub fn unpremultiply(pixels: &mut [u8]) {
+    for px in pixels.chunks_exact_mut(4) {
+        let a = px[3] as u32;
+        if a == 0 {
+            px[0] = 0;
+            px[1] = 0;
+            px[2] = 0;
+        } else if a < 255 {
+            for c in &mut px[..3] {
+                *c = ((*c as u32 * 255 + a / 2) / a).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// Converts straight-alpha RGBA8 to premultiplied alpha in place.
+pub fn premultiply(pixels: &mut [u8]) {
+    for px in pixels.chunks_exact_mut(4) {
+        let a = px[3] as u32;
+        if a < 255 {
+            for c in &mut px[..3] {
+                *c = ((*c as u32 * a + 127) / 255) as u8;
+            }
+        }
+    }
+}
+
+/// Composites premultiplied RGBA8 over an opaque `background` and drops the alpha channel.
+pub fn flatten(pixels: &[u8], background: [u8; 3]) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(pixels.len() / 4 * 3);
+    for px in pixels.chunks_exact(4) {
+        let inv_a = 255 - px[3] as u32;
+        for (c, bg) in px[..3].iter().zip(background.iter()) {
+            rgb.push((*c as u32 + (*bg as u32 * inv_a + 127) / 255).min(255) as u8);
+        }
+    }
+    rgb
+}
@@ -18,8 +18,9 @@ pub fn get_context(
     canvas: &HtmlCanvasElement,
     options: &ContextOptions,
 ) -> Result<WebGl2RenderingContext, JsValue> {
+    let options = serde_wasm_bindgen::to_value(options)?;
     let gl = canvas
-        .get_context_with_context_options("webgl2", &JsValue::from_serde(options).unwrap())
+        .get_context_with_context_options("webgl2", &options)
         .map_err(|_| JsValue::from_str("WebGl2 not supported"))?
         .unwrap()
         .unchecked_into::<WebGl2RenderingContext>();
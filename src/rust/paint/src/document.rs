@@ -0,0 +1,341 @@
+use super::brush::Brush;
+use miniz_oxide::inflate::core::{inflate_flags, DecompressorOxide};
+use miniz_oxide::inflate::{self, TINFLStatus};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+// File layout, all integers little endian:
+//   magic        4 bytes, "RPNT"
+//   version      u32
+//   manifest len u32
+//   manifest     UTF-8 JSON, see `Manifest`
+//   blobs        zlib-compressed layer pixels, addressed by offset and length
+//                relative to the end of the manifest
+const MAGIC: &[u8; 4] = b"RPNT";
+
+/// Version written by `DocumentWriter`. Bump it whenever the layout or meaning
+/// of the manifest changes, and teach `DocumentReader` to upgrade older versions.
+pub const DOCUMENT_VERSION: u32 = 1;
+
+/// Everything needed to restore a painting. Fields added in later versions
+/// must have serde defaults so older files keep loading.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub width: u32,
+    pub height: u32,
+    /// Layers from bottom to top.
+    pub layers: Vec<LayerRecord>,
+    #[serde(default)]
+    pub active_layer: usize,
+    #[serde(default)]
+    pub brush: Brush,
+    #[serde(default)]
+    pub eraser: Brush,
+    #[serde(default)]
+    pub palette: Vec<[f32; 4]>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerRecord {
+    pub name: String,
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend_mode: u32,
+    pub visible: bool,
+    pub locked: bool,
+    /// Location of the layer's pixels in the blob section: premultiplied
+    /// RGBA8, top row first, zlib-compressed.
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// Collects layer pixel blobs while a document is being written.
+pub struct DocumentWriter {
+    blobs: Vec<u8>,
+}
+
+impl DocumentWriter {
+    pub fn new() -> Self {
+        Self { blobs: Vec::new() }
+    }
+
+    /// Compresses and appends layer pixels, returning their `(offset, length)`.
+    pub fn add_pixels(&mut self, pixels: &[u8]) -> (usize, usize) {
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(pixels, 6);
+        let offset = self.blobs.len();
+        self.blobs.extend_from_slice(&compressed);
+        (offset, compressed.len())
+    }
+
+    pub fn finish(self, manifest: &Manifest) -> Result<Vec<u8>, String> {
+        let json = serde_json::to_vec(manifest)
+            .map_err(|err| format!("Document encode error: {}", err))?;
+        let mut bytes = Vec::with_capacity(12 + json.len() + self.blobs.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&DOCUMENT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&json);
+        bytes.extend_from_slice(&self.blobs);
+        Ok(bytes)
+    }
+}
+
+/// A parsed document whose layer pixels are decompressed on demand.
+pub struct DocumentReader<'a> {
+    pub manifest: Manifest,
+    blobs: &'a [u8],
+}
+
+impl<'a> DocumentReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, String> {
+        if bytes.len() < 12 || &bytes[..4] != MAGIC {
+            return Err("Not a painting document".into());
+        }
+        let read_u32 = |at: usize| {
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&bytes[at..at + 4]);
+            u32::from_le_bytes(word)
+        };
+        let version = read_u32(4);
+        if version > DOCUMENT_VERSION {
+            return Err(format!(
+                "Document version {} is newer than the supported version {}",
+                version, DOCUMENT_VERSION
+            ));
+        }
+        let manifest_end = (read_u32(8) as usize)
+            .checked_add(12)
+            .filter(|end| *end <= bytes.len())
+            .ok_or("Truncated document")?;
+        // version 1 is the only layout so far; older versions get upgraded here
+        let manifest: Manifest = serde_json::from_slice(&bytes[12..manifest_end])
+            .map_err(|err| format!("Document decode error: {}", err))?;
+        if manifest.layers.is_empty() || manifest.width == 0 || manifest.height == 0 {
+            return Err("Document has no pixels".into());
+        }
+        Ok(Self {
+            manifest,
+            blobs: &bytes[manifest_end..],
+        })
+    }
+
+    /// Decompresses the pixels of the layer at `index`.
+    pub fn layer_pixels(&self, index: usize) -> Result<Vec<u8>, String> {
+        let record = &self.manifest.layers[index];
+        let blob = record
+            .offset
+            .checked_add(record.length)
+            .and_then(|end| self.blobs.get(record.offset..end))
+            .ok_or("Truncated document")?;
+        let expected = (self.manifest.width as usize)
+            .checked_mul(self.manifest.height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or("Layer pixels do not match the document size")?;
+        // inflate into a buffer of exactly the layer's size, so a blob that
+        // expands to more than that fails instead of growing without bound
+        let mut pixels = vec![0; expected];
+        let mut decompressor = DecompressorOxide::new();
+        let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+            | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let (status, _, written) = inflate::core::decompress(
+            &mut decompressor,
+            blob,
+            &mut Cursor::new(pixels.as_mut_slice()),
+            flags,
+        );
+        match status {
+            TINFLStatus::Done if written == expected => Ok(pixels),
+            TINFLStatus::Done | TINFLStatus::HasMoreOutput => {
+                Err("Layer pixels do not match the document size".into())
+            }
+            _ => Err("Corrupt layer pixels in document".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, (offset, length): (usize, usize)) -> LayerRecord {
+        LayerRecord {
+            name: name.to_string(),
+            opacity: 0.5,
+            blend_mode: 1,
+            visible: true,
+            locked: false,
+            offset,
+            length,
+        }
+    }
+
+    // a 2x1 document with two layers
+    fn sample_document() -> (Vec<u8>, [Vec<u8>; 2]) {
+        let pixels = [
+            vec![255, 0, 0, 255, 0, 0, 0, 0],
+            vec![1, 2, 3, 4, 5, 6, 7, 8],
+        ];
+        let mut writer = DocumentWriter::new();
+        let layers = vec![
+            record("Background", writer.add_pixels(&pixels[0])),
+            record("Ink", writer.add_pixels(&pixels[1])),
+        ];
+        let manifest = Manifest {
+            width: 2,
+            height: 1,
+            layers,
+            active_layer: 1,
+            brush: Brush::default(),
+            eraser: Brush::default(),
+            palette: vec![[0.25, 0.5, 0.75, 1.0]],
+        };
+        (writer.finish(&manifest).unwrap(), pixels)
+    }
+
+    fn read_error(bytes: &[u8]) -> String {
+        match DocumentReader::new(bytes) {
+            Ok(_) => panic!("document should not parse"),
+            Err(err) => err,
+        }
+    }
+
+    fn layer_error(bytes: &[u8], index: usize) -> String {
+        let reader = DocumentReader::new(bytes).unwrap_or_else(|err| panic!("{}", err));
+        reader.layer_pixels(index).unwrap_err()
+    }
+
+    // replaces the manifest of a written document, keeping its blobs
+    fn with_manifest(bytes: &[u8], json: &str) -> Vec<u8> {
+        let manifest_len = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        let mut patched = bytes[..8].to_vec();
+        patched.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        patched.extend_from_slice(json.as_bytes());
+        patched.extend_from_slice(&bytes[12 + manifest_len..]);
+        patched
+    }
+
+    #[test]
+    fn round_trips_layers_and_settings() {
+        let (bytes, pixels) = sample_document();
+        let reader = DocumentReader::new(&bytes).unwrap_or_else(|err| panic!("{}", err));
+        let manifest = &reader.manifest;
+        assert_eq!((manifest.width, manifest.height), (2, 1));
+        assert_eq!(manifest.active_layer, 1);
+        assert_eq!(manifest.palette, vec![[0.25, 0.5, 0.75, 1.0]]);
+        assert_eq!(manifest.layers[1].name, "Ink");
+        assert_eq!(manifest.layers[1].blend_mode, 1);
+        for (index, expected) in pixels.iter().enumerate() {
+            assert_eq!(&reader.layer_pixels(index).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(read_error(b""), "Not a painting document");
+        assert_eq!(
+            read_error(b"PK\x03\x04 not a painting"),
+            "Not a painting document"
+        );
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let (mut bytes, _) = sample_document();
+        bytes[4..8].copy_from_slice(&(DOCUMENT_VERSION + 1).to_le_bytes());
+        assert!(read_error(&bytes).contains("newer than the supported version"));
+    }
+
+    #[test]
+    fn rejects_truncated_manifests() {
+        let (bytes, _) = sample_document();
+        assert_eq!(read_error(&bytes[..20]), "Truncated document");
+        let mut huge = bytes.clone();
+        huge[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_error(&huge), "Truncated document");
+    }
+
+    #[test]
+    fn rejects_corrupt_manifests() {
+        let (bytes, _) = sample_document();
+        let patched = with_manifest(&bytes, "{\"width\": 2");
+        assert!(read_error(&patched).starts_with("Document decode error"));
+        let empty = with_manifest(&bytes, "{\"width\": 2, \"height\": 1, \"layers\": []}");
+        assert_eq!(read_error(&empty), "Document has no pixels");
+    }
+
+    #[test]
+    fn rejects_truncated_layer_pixels() {
+        let (bytes, _) = sample_document();
+        assert_eq!(
+            layer_error(&bytes[..bytes.len() - 1], 1),
+            "Truncated document"
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_layer_offsets() {
+        let (bytes, _) = sample_document();
+        let json = format!(
+            "{{\"width\": 2, \"height\": 1, \"layers\": [{{\"name\": \"Layer\", \"opacity\": 1, \
+             \"visible\": true, \"locked\": false, \"offset\": {}, \"length\": 2}}]}}",
+            usize::MAX
+        );
+        assert_eq!(
+            layer_error(&with_manifest(&bytes, &json), 0),
+            "Truncated document"
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_layer_pixels() {
+        let (mut bytes, _) = sample_document();
+        // the first blob starts right after the manifest, with the zlib header
+        let manifest_len = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        bytes[12 + manifest_len] ^= 0xff;
+        assert_eq!(layer_error(&bytes, 0), "Corrupt layer pixels in document");
+    }
+
+    #[test]
+    fn rejects_layer_pixels_of_the_wrong_size() {
+        let mut writer = DocumentWriter::new();
+        let layers = vec![record("Layer", writer.add_pixels(&[0; 12]))];
+        let manifest = Manifest {
+            width: 2,
+            height: 1,
+            layers,
+            active_layer: 0,
+            brush: Brush::default(),
+            eraser: Brush::default(),
+            palette: Vec::new(),
+        };
+        let bytes = writer.finish(&manifest).unwrap();
+        assert_eq!(
+            layer_error(&bytes, 0),
+            "Layer pixels do not match the document size"
+        );
+    }
+
+    #[test]
+    fn rejects_layer_pixels_that_inflate_past_the_document_size() {
+        // 16 MiB of zeros compresses to a few kilobytes
+        let mut writer = DocumentWriter::new();
+        let layers = vec![record("Layer", writer.add_pixels(&vec![0; 16 << 20]))];
+        let manifest = Manifest {
+            width: 2,
+            height: 1,
+            layers,
+            active_layer: 0,
+            brush: Brush::default(),
+            eraser: Brush::default(),
+            palette: Vec::new(),
+        };
+        let bytes = writer.finish(&manifest).unwrap();
+        assert!(bytes.len() < 64 << 10);
+        assert_eq!(
+            layer_error(&bytes, 0),
+            "Layer pixels do not match the document size"
+        );
+    }
+}
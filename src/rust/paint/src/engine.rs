@@ -1,41 +1,161 @@
 use super::brush::Brush;
-use super::pointer_state::PointerState;
+use super::codec::{self, Image};
+use super::document::{DocumentReader, DocumentWriter, LayerRecord, Manifest};
+use super::eyedropper::{self, EyedropperOptions};
+use super::fill::{self, FillOptions, Mask};
+use super::gradient::{Gradient, GradientShape, GradientStop, MAX_STOPS};
+use super::history::{self, History, PixelEdit};
+use super::layer::{BlendMode, Layer, LayerInfo, LayerStack};
+use super::ora::{self, OraLayer};
+use super::pointer_state::{PointerSample, PointerState};
+use super::psd::{self, PsdLayer};
+use super::selection::{self, Selection, SelectionDrag, SelectionMode};
 use super::shader;
-use js_sys::Float32Array;
-use std::cell::RefCell;
+use super::shape::{self, Shape, ShapeKind, ShapeOptions};
+use super::stabilizer::{Stabilizer, StabilizerMode};
+use super::stroke::{Dab, Stroke};
+use super::texture::{Anchor, ImagePlacement, Rect, RenderTarget, Resampling};
+use super::tool::Tool;
+use super::transform::{Floating, Handle};
+use super::view::{Affine, View, ViewDrag, ViewGesture};
+use js_sys::{Float32Array, Function};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{
-    console, HtmlCanvasElement, MouseEvent, UiEvent, WebGl2RenderingContext as WGL2,
-    WebGlFramebuffer, WebGlProgram, WebGlTexture,
+    console, HtmlCanvasElement, MouseEvent, PointerEvent, UiEvent, WebGl2RenderingContext as WGL2,
+    WebGlProgram, WebGlTexture, WheelEvent,
 };
 
+// how close, in CSS pixels, a click must be to a polygon vertex to close it
+const SNAP_DISTANCE: f32 = 8.0;
+
+// milliseconds between steps of the selection's marching ants
+const ANTS_INTERVAL: i32 = 120;
+
+// the most a single resample pass shrinks by, which bounds the kernel's size
+const MAX_RESAMPLE_SCALE: i32 = 8;
+
+// how far, in document pixels, the pointer must move before a lasso places another vertex
+const LASSO_SPACING: f32 = 1.0;
+
 pub struct Engine {
     gl: Option<WGL2>,
     canvas: Option<HtmlCanvasElement>,
-    canvas_tex: Option<WebGlTexture>,
-    canvas_fb: Option<WebGlFramebuffer>,
-    tri_program: Option<WebGlProgram>,
+    // layers are composited by ping-ponging between these two targets
+    composite: Option<RenderTarget>,
+    composite_back: Option<RenderTarget>,
+    // whether the last composite ended in `composite_back`, so the canvas can
+    // be redrawn from it without compositing again
+    composite_in_back: Cell<bool>,
+    layers: LayerStack,
+    brush_program: Option<WebGlProgram>,
     quad_program: Option<WebGlProgram>,
+    canvas_program: Option<WebGlProgram>,
+    resample_program: Option<WebGlProgram>,
+    transform_program: Option<WebGlProgram>,
+    gradient_program: Option<WebGlProgram>,
     pointer_state: PointerState,
+    view: View,
+    view_drag: Option<ViewDrag>,
+    tool: Tool,
     brush: Brush,
+    eraser: Brush,
+    // whether the current stroke erases, from the tool or a stylus eraser end
+    erasing: bool,
+    fill_options: FillOptions,
+    wand_options: FillOptions,
+    eyedropper_options: EyedropperOptions,
+    // whether the pointer is down with the eyedropper
+    picking: bool,
+    // called with each picked color, once the engine is no longer borrowed
+    on_color_picked: Option<Function>,
+    picked_color: Option<[f32; 4]>,
+    selection: Selection,
+    // the selection's coverage in every channel, for the brush and canvas shaders
+    selection_target: Option<RenderTarget>,
+    selection_drag: Option<SelectionDrag>,
+    selection_mode: SelectionMode,
+    // softens the edge of new selection outlines, in pixels
+    selection_feather: f32,
+    // offset of the marching ants' stripes, advanced by a timer
+    ants_phase: f32,
+    shape_options: ShapeOptions,
+    // the shape being dragged out, or a polygon between clicks
+    shape: Option<Shape>,
+    // whether the pointer went down with a shape tool, so releasing it never
+    // ends a stroke, even when the click closed a polygon
+    shape_pressed: bool,
+    // pixels lifted off the active layer by the transform tool
+    floating: Option<Floating>,
+    gradient: Gradient,
+    // the start and end of the gradient being dragged out, in document pixels
+    gradient_line: Option<((f32, f32), (f32, f32))>,
+    palette: Vec<[f32; 4]>,
+    stroke: Option<Stroke>,
+    stabilizer: Stabilizer,
+    history: History,
+    // copy of the layer being edited, taken before the edit started
+    edit_backup: Option<RenderTarget>,
+    edit_layer: Option<u32>,
+    edit_rect: Rect,
 }
 
 impl Engine {
+    /// Creates an engine for a `width` x `height` document, defaulting to the
+    /// canvas size.
     pub fn new(
         gl: Option<WGL2>,
         canvas: Option<HtmlCanvasElement>,
+        width: Option<u32>,
+        height: Option<u32>,
     ) -> Result<Rc<RefCell<Self>>, JsValue> {
         let this = Rc::new(RefCell::new(Engine {
             gl,
             canvas,
-            canvas_tex: None,
-            canvas_fb: None,
-            tri_program: None,
+            composite: None,
+            composite_back: None,
+            composite_in_back: Cell::new(false),
+            layers: LayerStack::new(),
+            brush_program: None,
             quad_program: None,
+            canvas_program: None,
+            resample_program: None,
+            transform_program: None,
+            gradient_program: None,
             pointer_state: PointerState::new(),
+            view: View::new(),
+            view_drag: None,
+            tool: Tool::Brush,
             brush: Brush::new(&[0.5, 0.5, 0.5, 1.0])?,
+            eraser: Brush::default(),
+            erasing: false,
+            fill_options: FillOptions::default(),
+            wand_options: FillOptions::default(),
+            eyedropper_options: EyedropperOptions::default(),
+            picking: false,
+            on_color_picked: None,
+            picked_color: None,
+            selection: Selection::new(0, 0),
+            selection_target: None,
+            selection_drag: None,
+            selection_mode: SelectionMode::Replace,
+            selection_feather: 0.0,
+            ants_phase: 0.0,
+            shape_options: ShapeOptions::default(),
+            shape: None,
+            shape_pressed: false,
+            floating: None,
+            gradient: Gradient::default(),
+            gradient_line: None,
+            palette: Vec::new(),
+            stroke: None,
+            stabilizer: Stabilizer::new(StabilizerMode::None, 0.0),
+            history: History::new(history::DEFAULT_BUDGET),
+            edit_backup: None,
+            edit_layer: None,
+            edit_rect: Rect::new(0, 0, 0, 0),
         }));
 
         // set blend func, call glenable, etc
@@ -44,14 +164,17 @@ impl Engine {
         // set initial viewport size (initial canvas width &  clientWidth will not match)
         this.borrow().resize_canvas();
 
-        // create the render target for canvas composite
-        this.borrow_mut().create_canvas_fb()?;
+        // create the render target for canvas composite and the background layer
+        let (canvas_width, canvas_height) = this.borrow().get_canvas_size();
+        let width = width.unwrap_or(canvas_width.max(1.0) as u32) as i32;
+        let height = height.unwrap_or(canvas_height.max(1.0) as u32) as i32;
+        this.borrow_mut().create_canvas_fb(width, height)?;
 
         // compile all shaders
         this.borrow_mut().compile_shaders()?;
 
-        // clear default framebuffer
-        this.borrow().clear(1.0, 1.0, 1.0, 1.0);
+        // show the whole document
+        this.borrow_mut().fit_view();
 
         // add all event handlers
         Self::init_handlers(this.clone())?;
@@ -59,10 +182,1377 @@ impl Engine {
     }
 
     pub fn change_color(&mut self, color: &[f32]) -> Result<(), JsValue> {
-        self.brush = Brush::new(color)?;
+        self.brush.set_color(color)
+    }
+
+    pub fn set_brush_size(&mut self, size: f32) {
+        self.brush.size = size.clamp(1.0, 1000.0);
+    }
+
+    pub fn set_brush_hardness(&mut self, hardness: f32) {
+        self.brush.hardness = hardness.clamp(0.0, 1.0);
+    }
+
+    pub fn set_brush_opacity(&mut self, opacity: f32) {
+        self.brush.opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    pub fn set_brush_spacing(&mut self, percent: f32) {
+        self.brush.spacing = (percent / 100.0).clamp(0.01, 10.0);
+    }
+
+    pub fn set_stabilizer(&mut self, mode: StabilizerMode, strength: f32) {
+        self.stabilizer.set_mode(mode, strength);
+    }
+
+    // dynamics are shared by the brush and the eraser
+    pub fn set_pressure_dynamics(&mut self, size: f32, opacity: f32) {
+        self.brush.dynamics.size_pressure = size.clamp(0.0, 1.0);
+        self.brush.dynamics.opacity_pressure = opacity.clamp(0.0, 1.0);
+        self.eraser.dynamics = self.brush.dynamics;
+    }
+
+    pub fn set_tilt_dynamics(&mut self, size: f32, opacity: f32) {
+        self.brush.dynamics.size_tilt = size.clamp(0.0, 1.0);
+        self.brush.dynamics.opacity_tilt = opacity.clamp(0.0, 1.0);
+        self.eraser.dynamics = self.brush.dynamics;
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        if tool != self.tool {
+            self.settle_edits();
+        }
+        self.tool = tool;
+    }
+
+    pub fn tool(&self) -> Tool {
+        self.tool
+    }
+
+    pub fn set_fill_options(&mut self, options: FillOptions) {
+        self.fill_options = FillOptions {
+            expand: options.expand.min(fill::MAX_EXPAND),
+            ..options
+        };
+    }
+
+    /// Fills the region around document pixel `x, y` with the brush color.
+    pub fn bucket_fill(&mut self, x: f32, y: f32) -> Result<(), JsValue> {
+        self.settle_edits();
+        let layer = self.layers.active().unwrap();
+        if !layer.editable() {
+            return Err("The active layer is hidden or locked".into());
+        }
+        let mut mask = match self.flood_region(x, y, &self.fill_options)? {
+            Some(mask) => mask,
+            None => return Ok(()),
+        };
+        self.selection.restrict(&mut mask);
+        let rect = mask.bounds();
+        if rect.is_empty() {
+            return Ok(());
+        }
+
+        self.begin_edit()?;
+        let gl = self.gl.as_ref().unwrap();
+        let target = &self.layers.active().unwrap().target;
+        let mut pixels = target.read_pixels(gl, &rect)?;
+        codec::flip_rows(&mut pixels, rect.width as usize, rect.height as usize);
+        fill::apply(&mut pixels, &rect, &mask, self.brush.color);
+        codec::flip_rows(&mut pixels, rect.width as usize, rect.height as usize);
+        target.write_pixels(gl, &rect, &pixels)?;
+        self.mark_dirty(&rect);
+        self.commit_edit()?;
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn set_eyedropper_options(&mut self, options: EyedropperOptions) {
+        self.eyedropper_options = EyedropperOptions {
+            size: options.size.clamp(1, 101),
+            ..options
+        };
+    }
+
+    pub fn set_color_picked_callback(&mut self, callback: Option<Function>) {
+        self.on_color_picked = callback;
+    }
+
+    /// Sets the brush color from the pixels around document pixel `x, y`,
+    /// returning the color, or `None` if there was nothing opaque to pick.
+    pub fn pick_color(&mut self, x: f32, y: f32) -> Result<Option<[f32; 4]>, JsValue> {
+        let (width, height) = self.get_document_size();
+        if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+            return Ok(None);
+        }
+        let size = self.eyedropper_options.size as i32;
+        let rect = Rect::new(x as i32 - size / 2, y as i32 - size / 2, size, size)
+            .intersect(&Rect::new(0, 0, width, height));
+        let gl = self.gl.as_ref().unwrap();
+        let source = if self.eyedropper_options.sample_merged {
+            self.composite_layers()?
+        } else {
+            &self.layers.active().unwrap().target
+        };
+        // row order doesn't matter for an average
+        let pixels = source.read_pixels(gl, &rect)?;
+        let color = match eyedropper::average(&pixels) {
+            Some(color) => color,
+            None => return Ok(None),
+        };
+        self.brush.color = color;
+        self.picked_color = Some(color);
+        Ok(Some(color))
+    }
+
+    /// Hands the last picked color to the JS callback. The engine must not be
+    /// borrowed while it runs, as the callback may call back into the painter.
+    pub fn report_picked_color(this: &Rc<RefCell<Self>>) {
+        let (callback, color) = {
+            let mut engine = this.borrow_mut();
+            match (engine.on_color_picked.clone(), engine.picked_color.take()) {
+                (Some(callback), Some(color)) => (callback, color),
+                _ => return,
+            }
+        };
+        let rgba = Float32Array::from(&color[..]);
+        if callback.call1(&JsValue::NULL, &rgba).is_err() {
+            console::log_1(&"color picked callback error".into());
+        }
+    }
+
+    pub fn set_shape_options(&mut self, options: ShapeOptions) {
+        self.shape_options = options;
+        if self.draw_shape().is_err() {
+            console::log_1(&"engine.draw_shape error".into());
+        }
+    }
+
+    /// Commits the shape in progress, closing an open polygon.
+    pub fn finish_shape(&mut self) -> Result<(), JsValue> {
+        let shape = match self.shape.as_mut() {
+            Some(shape) => shape,
+            None => return Ok(()),
+        };
+        // a polygon needs more than its first click to be anything
+        if shape.vertex_count() < 2 && shape.kind == ShapeKind::Polygon {
+            self.cancel_shape();
+            return Ok(());
+        }
+        shape.finish();
+        self.draw_shape()?;
+        self.shape = None;
+        self.commit_edit()
+    }
+
+    /// Drops the shape in progress, restoring the layer beneath its preview.
+    pub fn cancel_shape(&mut self) {
+        if self.shape.take().is_none() {
+            return;
+        }
+        self.restore_edit_rect();
+        self.edit_layer = None;
+        self.redraw();
+    }
+
+    pub fn set_selection_mode(&mut self, mode: SelectionMode) {
+        self.selection_mode = mode;
+    }
+
+    pub fn set_selection_feather(&mut self, radius: f32) {
+        self.selection_feather = radius.clamp(0.0, 250.0);
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.selection.active
+    }
+
+    pub fn select_all(&mut self) -> Result<(), JsValue> {
+        self.selection_drag = None;
+        self.selection.select_all();
+        self.selection_changed()
+    }
+
+    pub fn select_none(&mut self) -> Result<(), JsValue> {
+        self.selection_drag = None;
+        self.selection.clear();
+        self.selection_changed()
+    }
+
+    pub fn invert_selection(&mut self) -> Result<(), JsValue> {
+        self.selection_drag = None;
+        self.selection.invert();
+        self.selection_changed()
+    }
+
+    /// Softens the edge of the current selection over about `radius` pixels.
+    pub fn feather_selection(&mut self, radius: f32) -> Result<(), JsValue> {
+        self.selection_drag = None;
+        self.selection.feather(radius.clamp(0.0, 250.0));
+        self.selection_changed()
+    }
+
+    pub fn set_magic_wand_options(&mut self, options: FillOptions) {
+        self.wand_options = options;
+    }
+
+    /// Selects the region around document pixel `x, y` whose colors match it,
+    /// combined with the current selection under the selection mode.
+    pub fn magic_wand(&mut self, x: f32, y: f32) -> Result<(), JsValue> {
+        let mut mask = match self.flood_region(x, y, &self.wand_options)? {
+            Some(mask) => mask,
+            None => return Ok(()),
+        };
+        mask.blur(self.selection_feather);
+        self.selection_drag = None;
+        let base = self.selection.clone();
+        let everywhere = self.selection.bounds();
+        self.selection.combine(
+            &base,
+            self.selection_mode,
+            &mask.coverage,
+            &everywhere,
+            &everywhere,
+        );
+        self.selection.settle();
+        self.selection_changed()
+    }
+
+    /// Lifts the selected pixels of the active layer, or all of them without a
+    /// selection, so they can be transformed.
+    pub fn begin_transform(&mut self) -> Result<(), JsValue> {
+        if self.floating.is_some() {
+            return Ok(());
+        }
+        if !self.layers.active().unwrap().editable() {
+            return Err("The active layer is hidden or locked".into());
+        }
+        self.cancel_shape();
+        let (width, height) = self.get_document_size();
+        let source = if self.selection.active {
+            self.selection.mask.bounds()
+        } else {
+            Rect::new(0, 0, width, height)
+        };
+        if source.is_empty() {
+            return Err("Nothing is selected".into());
+        }
+        self.begin_edit()?;
+        let gl = self.gl.as_ref().unwrap();
+        let layer = &self.layers.active().unwrap().target;
+        let lifted = RenderTarget::new(gl, source.width, source.height)?;
+        if self.selection.active {
+            // split the pixels between the floating copy and the hole left behind
+            let mut pixels = layer.read_pixels(gl, &source)?;
+            let (rows, row_len) = (source.height as usize, source.width as usize);
+            codec::flip_rows(&mut pixels, row_len, rows);
+            let mut taken = vec![0u8; pixels.len()];
+            for (i, (px, out)) in pixels
+                .chunks_exact_mut(4)
+                .zip(taken.chunks_exact_mut(4))
+                .enumerate()
+            {
+                let (x, y) = (
+                    source.x as usize + i % row_len,
+                    source.y as usize + i / row_len,
+                );
+                let coverage =
+                    self.selection.mask.coverage[y * self.selection.mask.width + x] as u32;
+                for (channel, lifted) in px.iter_mut().zip(out.iter_mut()) {
+                    *lifted = ((*channel as u32 * coverage + 127) / 255) as u8;
+                    *channel -= *lifted;
+                }
+            }
+            codec::flip_rows(&mut pixels, row_len, rows);
+            codec::flip_rows(&mut taken, row_len, rows);
+            lifted.write_pixels(gl, &lifted.rect(), &taken)?;
+            layer.write_pixels(gl, &source, &pixels)?;
+        } else {
+            lifted.copy_from(gl, layer);
+            layer.clear(gl, 0.0, 0.0, 0.0, 0.0);
+        }
+        let base = RenderTarget::new(gl, width, height)?;
+        base.copy_from(gl, layer);
+        let (left, top) = (source.x as f32, source.y as f32);
+        let (right, bottom) = (left + source.width as f32, top + source.height as f32);
+        self.floating = Some(Floating {
+            target: lifted,
+            base,
+            corners: [(left, top), (right, top), (right, bottom), (left, bottom)],
+            source,
+            selection: if self.selection.active {
+                Some(self.selection.clone())
+            } else {
+                None
+            },
+            drag: None,
+        });
+        self.mark_dirty(&source);
+        self.draw_floating(Resampling::Bilinear)
+    }
+
+    /// Changes the floating pixels' placement by `transform`, around their center.
+    pub fn transform_by(&mut self, transform: &Affine) -> Result<(), JsValue> {
+        let floating = self
+            .floating
+            .as_mut()
+            .ok_or("Nothing is being transformed")?;
+        floating.transform(transform);
+        self.draw_floating(Resampling::Bilinear)
+    }
+
+    /// Moves corner `index` (top left, top right, bottom right, bottom left) of
+    /// the floating pixels to document point `x, y`.
+    pub fn set_transform_corner(&mut self, index: usize, x: f32, y: f32) -> Result<(), JsValue> {
+        let floating = self
+            .floating
+            .as_mut()
+            .ok_or("Nothing is being transformed")?;
+        let corner = floating
+            .corners
+            .get_mut(index)
+            .ok_or("Corner index must be 0 to 3")?;
+        *corner = (x, y);
+        self.draw_floating(Resampling::Bilinear)
+    }
+
+    pub fn is_transforming(&self) -> bool {
+        self.floating.is_some()
+    }
+
+    /// Draws the floating pixels into the layer with `resampling` and records the edit.
+    pub fn commit_transform(&mut self, resampling: Resampling) -> Result<(), JsValue> {
+        if self.floating.is_none() {
+            return Ok(());
+        }
+        let result = self.draw_floating(resampling);
+        let floating = self.floating.take().unwrap();
+        if let Some(selection) = floating.transformed_selection() {
+            self.selection = selection;
+            self.selection_changed()?;
+        }
+        let gl = self.gl.as_ref().unwrap();
+        floating.target.delete(gl);
+        floating.base.delete(gl);
+        result?;
+        self.commit_edit()
+    }
+
+    /// Puts the floating pixels back where they were lifted from.
+    pub fn cancel_transform(&mut self) {
+        let floating = match self.floating.take() {
+            Some(floating) => floating,
+            None => return,
+        };
+        let gl = self.gl.as_ref().unwrap();
+        floating.target.delete(gl);
+        floating.base.delete(gl);
+        self.restore_edit_rect();
+        self.edit_layer = None;
+        self.redraw();
+    }
+
+    /// Sets the gradient's stops from flat groups of position and straight
+    /// RGBA, all in `[0, 1]`.
+    pub fn set_gradient_stops(&mut self, values: &[f32]) -> Result<(), JsValue> {
+        if values.is_empty() || !values.len().is_multiple_of(5) {
+            return Err("Gradient stops must be position and RGBA groups".into());
+        }
+        if values.len() / 5 > MAX_STOPS {
+            return Err(format!("Gradients have at most {} stops", MAX_STOPS).into());
+        }
+        let mut stops: Vec<GradientStop> = values
+            .chunks_exact(5)
+            .map(|v| GradientStop {
+                position: v[0].clamp(0.0, 1.0),
+                color: [v[1], v[2], v[3], v[4]].map(|c| c.clamp(0.0, 1.0)),
+            })
+            .collect();
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        self.gradient.stops = stops;
+        Ok(())
+    }
+
+    pub fn set_gradient_options(&mut self, shape: GradientShape, dither: bool) {
+        self.gradient.shape = shape;
+        self.gradient.dither = dither;
+    }
+
+    /// Fills the active layer, or the selection, with the gradient from
+    /// document point `start` to `end` as one undoable edit.
+    pub fn draw_gradient(&mut self, start: (f32, f32), end: (f32, f32)) -> Result<(), JsValue> {
+        self.settle_edits();
+        if !self.layers.active().unwrap().editable() {
+            return Err("The active layer is hidden or locked".into());
+        }
+        self.begin_edit()?;
+        self.gradient_line = Some((start, end));
+        let result = self.draw_gradient_line();
+        self.gradient_line = None;
+        result?;
+        self.commit_edit()
+    }
+
+    /// Drops the gradient being dragged out, restoring the layer beneath its preview.
+    pub fn cancel_gradient(&mut self) {
+        if self.gradient_line.take().is_none() {
+            return;
+        }
+        self.restore_edit_rect();
+        self.edit_layer = None;
+        self.redraw();
+    }
+
+    pub fn set_eraser_size(&mut self, size: f32) {
+        self.eraser.size = size.clamp(1.0, 1000.0);
+    }
+
+    pub fn set_eraser_hardness(&mut self, hardness: f32) {
+        self.eraser.hardness = hardness.clamp(0.0, 1.0);
+    }
+
+    pub fn set_eraser_opacity(&mut self, opacity: f32) {
+        self.eraser.opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    pub fn set_palette(&mut self, colors: &[f32]) -> Result<(), JsValue> {
+        if !colors.len().is_multiple_of(4) {
+            return Err("Palette colors must be RGBA quadruples".into());
+        }
+        self.palette = colors
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect();
+        Ok(())
+    }
+
+    pub fn palette(&self) -> Vec<f32> {
+        self.palette.iter().flatten().copied().collect()
+    }
+
+    pub fn set_zoom(&mut self, zoom: f32) {
+        let (document, canvas) = self.view_sizes();
+        let center = (canvas.0 / 2.0, canvas.1 / 2.0);
+        self.view.zoom_at(zoom, center, document, canvas);
+        self.redraw();
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.view.zoom
+    }
+
+    /// Pans by CSS pixels.
+    pub fn pan_by(&mut self, dx: f32, dy: f32) {
+        let ratio = self.pixel_ratio() as f32;
+        self.view.pan_by(dx * ratio, dy * ratio);
+        self.redraw();
+    }
+
+    pub fn set_rotation(&mut self, degrees: f32) {
+        let (document, canvas) = self.view_sizes();
+        let center = (canvas.0 / 2.0, canvas.1 / 2.0);
+        self.view
+            .rotate_at(degrees.to_radians(), center, document, canvas);
+        self.redraw();
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.view.rotation.to_degrees()
+    }
+
+    pub fn set_flip_horizontal(&mut self, flip: bool) {
+        let (document, canvas) = self.view_sizes();
+        let center = (canvas.0 / 2.0, canvas.1 / 2.0);
+        self.view.flip_at(flip, center, document, canvas);
+        self.redraw();
+    }
+
+    pub fn flip_horizontal(&self) -> bool {
+        self.view.flip
+    }
+
+    pub fn reset_view(&mut self) {
+        self.view = View::new();
+        self.redraw();
+    }
+
+    pub fn fit_view(&mut self) {
+        let (document, canvas) = self.view_sizes();
+        self.view.fit(document, canvas);
+        self.redraw();
+    }
+
+    pub fn document_size(&self) -> (i32, i32) {
+        self.get_document_size()
+    }
+
+    /// Starts over with a single white layer at the given size.
+    pub fn new_document(&mut self, width: u32, height: u32) -> Result<(), JsValue> {
+        if self.pointer_state.pressed() {
+            return Err("Cannot start a new document while drawing".into());
+        }
+        self.settle_edits();
+        self.check_document_size(width, height)?;
+        let (width, height) = (width as i32, height as i32);
+        let background = self.background_layer(width, height)?;
+        self.replace_layers(width, height, vec![background], 0)?;
+        self.fit_view();
+        Ok(())
+    }
+
+    pub fn add_layer(&mut self, name: Option<String>) -> Result<u32, JsValue> {
+        let (width, height) = self.get_document_size();
+        let target = RenderTarget::new(self.gl.as_ref().unwrap(), width, height)?;
+        let id = self.layers.next_id();
+        let name = name.unwrap_or_else(|| format!("Layer {}", id));
+        self.layers
+            .insert_above_active(Layer::new(id, name, target));
+        self.redraw();
+        Ok(id)
+    }
+
+    pub fn remove_layer(&mut self, id: u32) -> Result<(), JsValue> {
+        if self.layers.len() == 1 {
+            return Err("Cannot remove the last layer".into());
+        }
+        self.settle_edits();
+        let layer = self.layers.remove(id)?;
+        layer.target.delete(self.gl.as_ref().unwrap());
+        self.history.remove_layer(id);
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn move_layer(&mut self, id: u32, index: usize) -> Result<(), JsValue> {
+        self.layers.move_to(id, index)?;
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn rename_layer(&mut self, id: u32, name: String) -> Result<(), JsValue> {
+        self.layers.get_mut(id)?.name = name;
+        Ok(())
+    }
+
+    pub fn set_layer_blend_mode(&mut self, id: u32, mode: BlendMode) -> Result<(), JsValue> {
+        self.layers.get_mut(id)?.blend_mode = mode;
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn set_layer_visible(&mut self, id: u32, visible: bool) -> Result<(), JsValue> {
+        self.layers.get_mut(id)?.visible = visible;
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn set_layer_locked(&mut self, id: u32, locked: bool) -> Result<(), JsValue> {
+        self.layers.get_mut(id)?.locked = locked;
+        Ok(())
+    }
+
+    pub fn set_layer_opacity(&mut self, id: u32, opacity: f32) -> Result<(), JsValue> {
+        self.layers.get_mut(id)?.opacity = opacity.clamp(0.0, 1.0);
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn set_active_layer(&mut self, id: u32) -> Result<(), JsValue> {
+        self.settle_edits();
+        self.layers.set_active(id)
+    }
+
+    pub fn active_layer(&self) -> u32 {
+        self.layers.active().unwrap().id
+    }
+
+    pub fn layer_infos(&self) -> Vec<LayerInfo> {
+        self.layers.infos()
+    }
+
+    pub fn undo(&mut self) -> Result<(), JsValue> {
+        if self.pointer_state.pressed() {
+            return Ok(());
+        }
+        if self.shape.is_some() || self.floating.is_some() {
+            // undoing an unfinished shape or transform discards it
+            self.cancel_shape();
+            self.cancel_transform();
+            return Ok(());
+        }
+        // the entry only moves once it has been applied, so a failed write loses nothing
+        let edit = match self.history.next_undo() {
+            Some(edit) => edit,
+            None => return Ok(()),
+        };
+        let layer = self.layers.get(edit.layer_id)?;
+        layer
+            .target
+            .write_pixels(self.gl.as_ref().unwrap(), &edit.rect, &edit.before)?;
+        self.history.undo();
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn redo(&mut self) -> Result<(), JsValue> {
+        if self.pointer_state.pressed() {
+            return Ok(());
+        }
+        self.cancel_shape();
+        self.cancel_transform();
+        let edit = match self.history.next_redo() {
+            Some(edit) => edit,
+            None => return Ok(()),
+        };
+        let layer = self.layers.get(edit.layer_id)?;
+        layer
+            .target
+            .write_pixels(self.gl.as_ref().unwrap(), &edit.rect, &edit.after)?;
+        self.history.redo();
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    pub fn set_history_budget(&mut self, bytes: usize) {
+        self.history.set_budget(bytes);
+    }
+
+    pub fn export_png(&self, transparent: bool) -> Result<Vec<u8>, JsValue> {
+        let (width, height) = self.get_document_size();
+        let mut pixels = self.read_composite()?;
+        if transparent {
+            codec::unpremultiply(&mut pixels);
+            codec::encode_png(width as u32, height as u32, 4, &pixels)
+        } else {
+            let rgb = codec::flatten(&pixels, [255, 255, 255]);
+            codec::encode_png(width as u32, height as u32, 3, &rgb)
+        }
+    }
+
+    pub fn import_image(
+        &mut self,
+        bytes: &[u8],
+        as_layer: bool,
+        placement: ImagePlacement,
+    ) -> Result<u32, JsValue> {
+        let image = codec::decode_image(bytes)?;
+        self.check_document_size(image.width, image.height)?;
+        let (width, height) = (image.width as i32, image.height as i32);
+        let mut pixels = image.pixels;
+        codec::premultiply(&mut pixels);
+        codec::flip_rows(&mut pixels, width as usize, height as usize);
+
+        if !as_layer && !self.layers.active().unwrap().editable() {
+            return Err("The active layer is hidden or locked".into());
+        }
+        let gl = self.gl.as_ref().unwrap();
+        let source = RenderTarget::new(gl, width, height)?;
+        if let Err(err) = source.write_pixels(gl, &source.rect(), &pixels) {
+            source.delete(gl);
+            return Err(err);
+        }
+        source.generate_mipmaps(gl);
+
+        let started = if as_layer {
+            self.add_layer(Some(String::from("Imported image")))
+        } else {
+            self.begin_edit().map(|_| self.layers.active().unwrap().id)
+        };
+        let layer_id = match started {
+            Ok(id) => id,
+            Err(err) => {
+                source.delete(self.gl.as_ref().unwrap());
+                return Err(err);
+            }
+        };
+
+        let gl = self.gl.as_ref().unwrap();
+        let target = &self.layers.get(layer_id)?.target;
+        target.clear(gl, 0.0, 0.0, 0.0, 0.0);
+        let (doc_width, doc_height) = self.get_document_size();
+        let [x, y, w, h] = placement.place(
+            width as f32,
+            height as f32,
+            doc_width as f32,
+            doc_height as f32,
+        );
+        target.bind(gl);
+        let result = self.draw_texture_rect(&source.texture, target, x, y, w, h);
+        source.delete(gl);
+        let rect = target.rect();
+        if !as_layer {
+            self.mark_dirty(&rect);
+            if let Err(err) = result {
+                // put back what the clear wiped
+                self.restore_edit_rect();
+                self.edit_layer = None;
+                self.redraw();
+                return Err(err);
+            }
+            self.commit_edit()?;
+        }
+        result?;
+        self.redraw();
+        Ok(layer_id)
+    }
+
+    /// The selected pixels of the active layer, or the whole layer without a
+    /// selection, cropped to the selection's bounds, as PNG bytes.
+    pub fn copy(&self) -> Result<Vec<u8>, JsValue> {
+        let (rect, mut pixels) = self.read_selected()?;
+        codec::unpremultiply(&mut pixels);
+        codec::encode_png(rect.width as u32, rect.height as u32, 4, &pixels)
+    }
+
+    /// Copies, then clears the selected pixels as one undoable edit.
+    pub fn cut(&mut self) -> Result<Vec<u8>, JsValue> {
+        self.settle_edits();
+        if !self.layers.active().unwrap().editable() {
+            return Err("The active layer is hidden or locked".into());
+        }
+        let bytes = self.copy()?;
+        let rect = self.selected_rect();
+        self.begin_edit()?;
+        let gl = self.gl.as_ref().unwrap();
+        let pixels = if self.selection.active {
+            // keep what the selection leaves unselected
+            let mut pixels = self
+                .layers
+                .active()
+                .unwrap()
+                .target
+                .read_pixels(gl, &rect)?;
+            codec::flip_rows(&mut pixels, rect.width as usize, rect.height as usize);
+            self.selection.mask_pixels(&mut pixels, &rect, true);
+            codec::flip_rows(&mut pixels, rect.width as usize, rect.height as usize);
+            pixels
+        } else {
+            vec![0; rect.byte_len()]
+        };
+        self.layers
+            .active()
+            .unwrap()
+            .target
+            .write_pixels(gl, &rect, &pixels)?;
+        self.mark_dirty(&rect);
+        self.commit_edit()?;
+        self.redraw();
+        Ok(bytes)
+    }
+
+    /// Decodes PNG or JPEG bytes onto a new layer as floating pixels centered
+    /// in the view, ready for the transform tool, returning the layer's id.
+    pub fn paste(&mut self, bytes: &[u8]) -> Result<u32, JsValue> {
+        self.settle_edits();
+        let image = codec::decode_image(bytes)?;
+        self.check_document_size(image.width, image.height)?;
+        let (width, height) = (image.width as i32, image.height as i32);
+        let mut pixels = image.pixels;
+        codec::premultiply(&mut pixels);
+        codec::flip_rows(&mut pixels, width as usize, height as usize);
+
+        let gl = self.gl.as_ref().unwrap();
+        let target = RenderTarget::new(gl, width, height)?;
+        if let Err(err) = target.write_pixels(gl, &target.rect(), &pixels) {
+            target.delete(gl);
+            return Err(err);
+        }
+        let (doc_width, doc_height) = self.get_document_size();
+        let base = match RenderTarget::new(gl, doc_width, doc_height) {
+            Ok(base) => base,
+            Err(err) => {
+                target.delete(gl);
+                return Err(err);
+            }
+        };
+        let previous_id = self.layers.active().unwrap().id;
+        let layer_id = match self.add_layer(Some(String::from("Pasted"))) {
+            Ok(id) => id,
+            Err(err) => {
+                let gl = self.gl.as_ref().unwrap();
+                target.delete(gl);
+                base.delete(gl);
+                return Err(err);
+            }
+        };
+        if let Err(err) = self.begin_edit() {
+            let gl = self.gl.as_ref().unwrap();
+            target.delete(gl);
+            base.delete(gl);
+            if let Ok(layer) = self.layers.remove(layer_id) {
+                layer.target.delete(gl);
+            }
+            self.layers.set_active(previous_id)?;
+            self.redraw();
+            return Err(err);
+        }
+
+        // whole pixels, so an unmoved paste stays crisp
+        let (document, canvas) = self.view_sizes();
+        let (cx, cy) = self
+            .view
+            .canvas_to_document(document, canvas)
+            .apply(canvas.0 / 2.0, canvas.1 / 2.0);
+        let left = (cx - width as f32 / 2.0).round();
+        let top = (cy - height as f32 / 2.0).round();
+        let (right, bottom) = (left + width as f32, top + height as f32);
+        self.floating = Some(Floating {
+            target,
+            base,
+            corners: [(left, top), (right, top), (right, bottom), (left, bottom)],
+            source: Rect::new(left as i32, top as i32, width, height),
+            selection: None,
+            drag: None,
+        });
+        self.draw_floating(Resampling::Bilinear)?;
+        Ok(layer_id)
+    }
+
+    pub fn save_document(&self) -> Result<Vec<u8>, JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let (width, height) = self.get_document_size();
+        let mut writer = DocumentWriter::new();
+        let mut records = Vec::with_capacity(self.layers.len());
+        for layer in self.layers.iter() {
+            let mut pixels = layer.target.read_pixels(gl, &layer.target.rect())?;
+            codec::flip_rows(&mut pixels, width as usize, height as usize);
+            let (offset, length) = writer.add_pixels(&pixels);
+            records.push(LayerRecord {
+                name: layer.name.clone(),
+                opacity: layer.opacity,
+                blend_mode: layer.blend_mode as u32,
+                visible: layer.visible,
+                locked: layer.locked,
+                offset,
+                length,
+            });
+        }
+        let active_id = self.layers.active().unwrap().id;
+        let manifest = Manifest {
+            width: width as u32,
+            height: height as u32,
+            layers: records,
+            active_layer: self.layers.index_of(active_id)?,
+            brush: self.brush.clone(),
+            eraser: self.eraser.clone(),
+            palette: self.palette.clone(),
+        };
+        Ok(writer.finish(&manifest)?)
+    }
+
+    pub fn load_document(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        if self.pointer_state.pressed() {
+            return Err("Cannot load a document while drawing".into());
+        }
+        self.settle_edits();
+        let reader = DocumentReader::new(bytes)?;
+        let manifest = &reader.manifest;
+        self.check_document_size(manifest.width, manifest.height)?;
+        let (width, height) = (manifest.width as i32, manifest.height as i32);
+        let gl = self.gl.as_ref().unwrap();
+
+        let mut layers: Vec<Layer> = Vec::with_capacity(manifest.layers.len());
+        for (index, record) in manifest.layers.iter().enumerate() {
+            let pixels = reader.layer_pixels(index).map_err(JsValue::from);
+            let layer = pixels.and_then(|mut pixels| {
+                codec::flip_rows(&mut pixels, width as usize, height as usize);
+                let target = RenderTarget::new(gl, width, height)?;
+                if let Err(err) = target.write_pixels(gl, &target.rect(), &pixels) {
+                    target.delete(gl);
+                    return Err(err);
+                }
+                Ok(target)
+            });
+            let target = match layer {
+                Ok(target) => target,
+                Err(err) => {
+                    for layer in layers.iter() {
+                        layer.target.delete(gl);
+                    }
+                    return Err(err);
+                }
+            };
+            let mut layer = Layer::new(self.layers.next_id(), record.name.clone(), target);
+            layer.opacity = record.opacity.clamp(0.0, 1.0);
+            layer.blend_mode = BlendMode::from_u32(record.blend_mode).unwrap_or(BlendMode::Normal);
+            layer.visible = record.visible;
+            layer.locked = record.locked;
+            layers.push(layer);
+        }
+
+        self.replace_layers(width, height, layers, manifest.active_layer)?;
+        self.load_brushes(&manifest.brush, &manifest.eraser);
+        self.palette = manifest.palette.clone();
+        self.fit_view();
         Ok(())
     }
 
+    pub fn export_ora(&self) -> Result<Vec<u8>, JsValue> {
+        let (width, height) = self.get_document_size();
+        let mut layers = Vec::with_capacity(self.layers.len());
+        for layer in self.layers.iter() {
+            layers.push(OraLayer {
+                name: layer.name.clone(),
+                opacity: layer.opacity,
+                visible: layer.visible,
+                blend_mode: layer.blend_mode,
+                image: self.read_layer_image(layer)?,
+            });
+        }
+        let merged = self.read_merged_image()?;
+        ora::encode(width as u32, height as u32, &layers, &merged)
+    }
+
+    pub fn export_psd(&self) -> Result<Vec<u8>, JsValue> {
+        let (width, height) = self.get_document_size();
+        let mut layers = Vec::with_capacity(self.layers.len());
+        for layer in self.layers.iter() {
+            layers.push(PsdLayer {
+                name: layer.name.clone(),
+                opacity: layer.opacity,
+                visible: layer.visible,
+                blend_mode: layer.blend_mode,
+                image: self.read_layer_image(layer)?,
+            });
+        }
+        let merged = self.read_merged_image()?;
+        psd::encode(width as u32, height as u32, &layers, &merged)
+    }
+
+    pub fn import_ora(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        if self.pointer_state.pressed() {
+            return Err("Cannot load a document while drawing".into());
+        }
+        self.settle_edits();
+        let document = ora::decode(bytes, self.max_texture_size())?;
+        self.check_document_size(document.width, document.height)?;
+        let (width, height) = (document.width as i32, document.height as i32);
+        let gl = self.gl.as_ref().unwrap();
+
+        let mut layers: Vec<Layer> = Vec::with_capacity(document.layers.len());
+        for record in document.layers {
+            let mut pixels = record.image.pixels;
+            codec::premultiply(&mut pixels);
+            codec::flip_rows(&mut pixels, width as usize, height as usize);
+            let target = RenderTarget::new(gl, width, height).and_then(|target| {
+                match target.write_pixels(gl, &target.rect(), &pixels) {
+                    Ok(_) => Ok(target),
+                    Err(err) => {
+                        target.delete(gl);
+                        Err(err)
+                    }
+                }
+            });
+            let target = match target {
+                Ok(target) => target,
+                Err(err) => {
+                    for layer in layers.iter() {
+                        layer.target.delete(gl);
+                    }
+                    return Err(err);
+                }
+            };
+            let mut layer = Layer::new(self.layers.next_id(), record.name, target);
+            layer.opacity = record.opacity;
+            layer.blend_mode = record.blend_mode;
+            layer.visible = record.visible;
+            layers.push(layer);
+        }
+
+        let top = layers.len() - 1;
+        self.replace_layers(width, height, layers, top)?;
+        self.fit_view();
+        Ok(())
+    }
+
+    /// Changes the document size without scaling, keeping the content where
+    /// `anchor` says. New areas are transparent.
+    pub fn resize_document(
+        &mut self,
+        width: u32,
+        height: u32,
+        anchor: Anchor,
+    ) -> Result<(), JsValue> {
+        self.check_document_size(width, height)?;
+        let (old_width, old_height) = self.get_document_size();
+        let (fx, fy) = anchor.factors();
+        let x = ((width as i32 - old_width) as f32 * fx).round() as i32;
+        let y = ((height as i32 - old_height) as f32 * fy).round() as i32;
+        self.reallocate(width as i32, height as i32, x, y)
+    }
+
+    /// Shrinks the document to `rect`, clipped to the current bounds.
+    pub fn crop(&mut self, rect: &Rect) -> Result<(), JsValue> {
+        let (width, height) = self.get_document_size();
+        let rect = rect.intersect(&Rect::new(0, 0, width, height));
+        if rect.is_empty() {
+            return Err("Crop rect is outside the document".into());
+        }
+        self.reallocate(rect.width, rect.height, -rect.x, -rect.y)
+    }
+
+    /// Scales every layer to the new size.
+    pub fn scale_document(
+        &mut self,
+        width: u32,
+        height: u32,
+        resampling: Resampling,
+    ) -> Result<(), JsValue> {
+        if self.pointer_state.pressed() {
+            return Err("Cannot change the document while drawing".into());
+        }
+        self.settle_edits();
+        self.check_document_size(width, height)?;
+        let (width, height) = (width as i32, height as i32);
+        let (old_width, old_height) = self.get_document_size();
+        let gl = self.gl.as_ref().unwrap();
+
+        let program = self.resample_program.as_ref().unwrap();
+        gl.use_program(Some(program));
+        self.set_quad_transform(program, &Affine::identity());
+        let uniform_loc = gl.get_uniform_location(program, "kernel");
+        gl.uniform1i(uniform_loc.as_ref(), resampling as i32);
+        let scale_loc = gl.get_uniform_location(program, "scale");
+
+        // large reductions take several passes, each shrinking by at most MAX_RESAMPLE_SCALE
+        let next =
+            |from: i32, to: i32| to.max((from + MAX_RESAMPLE_SCALE - 1) / MAX_RESAMPLE_SCALE);
+        let mut sizes = Vec::new();
+        let mut from = (old_width, old_height);
+        loop {
+            let to = (next(from.0, width), next(from.1, height));
+            sizes.push((from, to));
+            if to == (width, height) {
+                break;
+            }
+            from = to;
+        }
+
+        let mut targets: Vec<RenderTarget> = Vec::with_capacity(self.layers.len());
+        gl.disable(WGL2::BLEND);
+        let result = self.layers.iter().try_for_each(|layer| {
+            let mut source: Option<RenderTarget> = None;
+            for ((from_width, from_height), (to_width, to_height)) in sizes.iter() {
+                let target = match RenderTarget::new(gl, *to_width, *to_height) {
+                    Ok(target) => target,
+                    Err(err) => {
+                        source.iter().for_each(|source| source.delete(gl));
+                        return Err(err);
+                    }
+                };
+                gl.uniform2f(
+                    scale_loc.as_ref(),
+                    (*from_width as f32 / *to_width as f32).max(1.0),
+                    (*from_height as f32 / *to_height as f32).max(1.0),
+                );
+                target.bind(gl);
+                let texture = &source.as_ref().unwrap_or(&layer.target).texture;
+                gl.bind_texture(WGL2::TEXTURE_2D, Some(texture));
+                let result = self.draw_quad();
+                gl.bind_texture(WGL2::TEXTURE_2D, None);
+                if let Some(previous) = source.replace(target) {
+                    previous.delete(gl);
+                }
+                if let Err(err) = result {
+                    source.iter().for_each(|source| source.delete(gl));
+                    return Err(err);
+                }
+            }
+            targets.push(source.unwrap());
+            Ok(())
+        });
+        gl.enable(WGL2::BLEND);
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
+        if let Err(err) = result {
+            targets.iter().for_each(|target| target.delete(gl));
+            return Err(err);
+        }
+        self.replace_targets(width, height, targets)?;
+        self.refresh_view();
+        Ok(())
+    }
+
+    // copies every layer into a `width` x `height` target at offset `x, y`
+    fn reallocate(&mut self, width: i32, height: i32, x: i32, y: i32) -> Result<(), JsValue> {
+        if self.pointer_state.pressed() {
+            return Err("Cannot change the document while drawing".into());
+        }
+        self.settle_edits();
+        let gl = self.gl.as_ref().unwrap();
+        let mut targets: Vec<RenderTarget> = Vec::with_capacity(self.layers.len());
+        for layer in self.layers.iter() {
+            let target = match RenderTarget::new(gl, width, height) {
+                Ok(target) => target,
+                Err(err) => {
+                    targets.iter().for_each(|target| target.delete(gl));
+                    return Err(err);
+                }
+            };
+            target.copy_rect_from(gl, &layer.target, &layer.target.rect(), x, y);
+            targets.push(target);
+        }
+        self.replace_targets(width, height, targets)?;
+        self.refresh_view();
+        Ok(())
+    }
+
+    // redraws after the document changed size, refitting if the view was fitted
+    fn refresh_view(&mut self) {
+        if self.view.fitted {
+            self.fit_view();
+        } else {
+            self.redraw();
+        }
+    }
+
+    // the largest document side the GPU can hold in one texture
+    fn max_texture_size(&self) -> u32 {
+        let gl = self.gl.as_ref().unwrap();
+        gl.get_parameter(WGL2::MAX_TEXTURE_SIZE)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .unwrap_or(4096.0) as u32
+    }
+
+    // takes brush settings from a file through the same clamps as the setters
+    fn load_brushes(&mut self, brush: &Brush, eraser: &Brush) {
+        self.brush.color = brush.color.map(|c| c.clamp(0.0, 1.0));
+        self.set_brush_size(brush.size);
+        self.set_brush_hardness(brush.hardness);
+        self.set_brush_opacity(brush.opacity);
+        self.set_brush_spacing(brush.spacing * 100.0);
+        let dynamics = brush.dynamics;
+        self.set_pressure_dynamics(dynamics.size_pressure, dynamics.opacity_pressure);
+        self.set_tilt_dynamics(dynamics.size_tilt, dynamics.opacity_tilt);
+        self.set_eraser_size(eraser.size);
+        self.set_eraser_hardness(eraser.hardness);
+        self.set_eraser_opacity(eraser.opacity);
+        self.eraser.spacing = eraser.spacing.clamp(0.01, 10.0);
+    }
+
+    // rejects sizes the GPU can't hold in a single texture
+    fn check_document_size(&self, width: u32, height: u32) -> Result<(), JsValue> {
+        let max_size = self.max_texture_size();
+        if width == 0 || height == 0 || width > max_size || height > max_size {
+            return Err(JsValue::from_str(
+                format!(
+                    "Document size {}x{} is outside 1x1 to {}x{}",
+                    width, height, max_size, max_size
+                )
+                .as_str(),
+            ));
+        }
+        Ok(())
+    }
+
+    // swaps in a new set of layers at the given document size, discarding history
+    fn replace_layers(
+        &mut self,
+        width: i32,
+        height: i32,
+        layers: Vec<Layer>,
+        active: usize,
+    ) -> Result<(), JsValue> {
+        self.resize_composites(width, height)?;
+        let gl = self.gl.as_ref().unwrap();
+        for layer in self.layers.replace(layers, active) {
+            layer.target.delete(gl);
+        }
+        self.edit_layer = None;
+        self.selection_drag = None;
+        self.selection.clear();
+        self.history.clear();
+        Ok(())
+    }
+
+    // gives every layer a new target, in stack order, discarding history
+    fn replace_targets(
+        &mut self,
+        width: i32,
+        height: i32,
+        targets: Vec<RenderTarget>,
+    ) -> Result<(), JsValue> {
+        self.resize_composites(width, height)?;
+        let gl = self.gl.as_ref().unwrap();
+        for (layer, target) in self.layers.iter_mut().zip(targets) {
+            std::mem::replace(&mut layer.target, target).delete(gl);
+        }
+        self.edit_layer = None;
+        self.selection_drag = None;
+        self.selection.clear();
+        self.history.clear();
+        Ok(())
+    }
+
+    // reallocates the composites (and drops the edit backup) for a new document size
+    fn resize_composites(&mut self, width: i32, height: i32) -> Result<(), JsValue> {
+        if self.get_document_size() == (width, height) {
+            return Ok(());
+        }
+        let gl = self.gl.as_ref().unwrap();
+        let composite = RenderTarget::new(gl, width, height)?;
+        let composite_back = RenderTarget::new(gl, width, height)?;
+        let selection_target = RenderTarget::new(gl, width, height)?;
+        self.selection = Selection::new(width as usize, height as usize);
+        for old in [
+            self.composite.replace(composite),
+            self.composite_back.replace(composite_back),
+            self.selection_target.replace(selection_target),
+            self.edit_backup.take(),
+        ]
+        .iter()
+        .flatten()
+        {
+            old.delete(gl);
+        }
+        Ok(())
+    }
+
+    // a layer's pixels as straight-alpha RGBA8, top row first
+    fn read_layer_image(&self, layer: &Layer) -> Result<Image, JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let target = &layer.target;
+        let mut pixels = target.read_pixels(gl, &target.rect())?;
+        codec::flip_rows(&mut pixels, target.width as usize, target.height as usize);
+        codec::unpremultiply(&mut pixels);
+        Ok(Image {
+            width: target.width as u32,
+            height: target.height as u32,
+            pixels,
+        })
+    }
+
+    // the selection's bounds, or the whole active layer without a selection
+    fn selected_rect(&self) -> Rect {
+        if self.selection.active {
+            self.selection.mask.bounds()
+        } else {
+            self.layers.active().unwrap().target.rect()
+        }
+    }
+
+    // the selected pixels of the active layer as premultiplied RGBA8, top row
+    // first, and the rect they cover
+    fn read_selected(&self) -> Result<(Rect, Vec<u8>), JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let target = &self.layers.active().unwrap().target;
+        let rect = self.selected_rect();
+        if rect.is_empty() {
+            return Err("Nothing is selected".into());
+        }
+        let mut pixels = target.read_pixels(gl, &rect)?;
+        codec::flip_rows(&mut pixels, rect.width as usize, rect.height as usize);
+        if self.selection.active {
+            self.selection.mask_pixels(&mut pixels, &rect, false);
+        }
+        Ok((rect, pixels))
+    }
+
+    // the composited document as straight-alpha RGBA8, top row first
+    fn read_merged_image(&self) -> Result<Image, JsValue> {
+        let (width, height) = self.get_document_size();
+        let mut pixels = self.read_composite()?;
+        codec::unpremultiply(&mut pixels);
+        Ok(Image {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        })
+    }
+
+    // the composited document as premultiplied RGBA8, top row first
+    fn read_composite(&self) -> Result<Vec<u8>, JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let composite = self.composite_layers()?;
+        let mut pixels = composite.read_pixels(gl, &composite.rect())?;
+        codec::flip_rows(
+            &mut pixels,
+            composite.width as usize,
+            composite.height as usize,
+        );
+        Ok(pixels)
+    }
+
+    // snapshots the active layer so the edit that follows can be recorded
+    fn begin_edit(&mut self) -> Result<(), JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let layer = self.layers.active().unwrap();
+        let target = &layer.target;
+        let reuse = match self.edit_backup.as_ref() {
+            Some(backup) => backup.width == target.width && backup.height == target.height,
+            None => false,
+        };
+        if !reuse {
+            if let Some(backup) = self.edit_backup.take() {
+                backup.delete(gl);
+            }
+            self.edit_backup = Some(RenderTarget::new(gl, target.width, target.height)?);
+        }
+        self.edit_backup.as_ref().unwrap().copy_from(gl, target);
+        self.edit_layer = Some(layer.id);
+        self.edit_rect = Rect::new(0, 0, 0, 0);
+        Ok(())
+    }
+
+    fn mark_dirty(&mut self, rect: &Rect) {
+        self.edit_rect = self.edit_rect.union(rect);
+    }
+
+    // records the region changed since `begin_edit` as one history entry
+    fn commit_edit(&mut self) -> Result<(), JsValue> {
+        let layer_id = match self.edit_layer.take() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let gl = self.gl.as_ref().unwrap();
+        let target = &self.layers.get(layer_id)?.target;
+        let rect = self.edit_rect.intersect(&target.rect());
+        if rect.is_empty() {
+            return Ok(());
+        }
+        let before = self.edit_backup.as_ref().unwrap().read_pixels(gl, &rect)?;
+        let after = target.read_pixels(gl, &rect)?;
+        self.history.push(PixelEdit {
+            layer_id,
+            rect,
+            before,
+            after,
+        });
+        Ok(())
+    }
+
+    // puts back the pixels changed since `begin_edit`
+    fn restore_edit_rect(&mut self) {
+        let layer_id = match self.edit_layer {
+            Some(id) => id,
+            None => return,
+        };
+        let gl = self.gl.as_ref().unwrap();
+        let target = match self.layers.get(layer_id) {
+            Ok(layer) => &layer.target,
+            Err(_) => return,
+        };
+        let rect = self.edit_rect.intersect(&target.rect());
+        if !rect.is_empty() {
+            target.copy_rect_from(
+                gl,
+                self.edit_backup.as_ref().unwrap(),
+                &rect,
+                rect.x,
+                rect.y,
+            );
+        }
+        self.edit_rect = Rect::new(0, 0, 0, 0);
+    }
+
     fn clear(&self, r: f32, g: f32, b: f32, a: f32) {
         let gl = self.gl.as_ref().unwrap();
         gl.clear_color(r, g, b, a);
@@ -72,7 +1562,8 @@ impl Engine {
     fn set_gl_capabilities(&self) {
         let gl = self.gl.as_ref().unwrap();
         gl.enable(WGL2::BLEND);
-        gl.blend_func(WGL2::SRC_ALPHA, WGL2::ONE_MINUS_SRC_ALPHA);
+        // layers store premultiplied alpha
+        gl.blend_func(WGL2::ONE, WGL2::ONE_MINUS_SRC_ALPHA);
     }
 
     fn get_canvas_size(&self) -> (f32, f32) {
@@ -82,65 +1573,60 @@ impl Engine {
         (width, height)
     }
 
-    fn create_canvas_fb(&mut self) -> Result<(), JsValue> {
+    // document and canvas sizes in pixels, as the view expects them
+    fn view_sizes(&self) -> ((f32, f32), (f32, f32)) {
+        let (width, height) = self.get_document_size();
+        ((width as f32, height as f32), self.get_canvas_size())
+    }
+
+    fn get_document_size(&self) -> (i32, i32) {
+        let composite = self.composite.as_ref().unwrap();
+        (composite.width, composite.height)
+    }
+
+    fn create_canvas_fb(&mut self, width: i32, height: i32) -> Result<(), JsValue> {
+        self.check_document_size(width as u32, height as u32)?;
         let gl = self.gl.as_ref().unwrap();
-        let canvas = self.canvas.as_ref().unwrap();
-        let width = canvas.width() as i32;
-        let height = canvas.height() as i32;
-        let level = 0;
-        let border = 0;
-        let texture = gl.create_texture();
-        gl.bind_texture(WGL2::TEXTURE_2D, texture.as_ref());
-        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-            WGL2::TEXTURE_2D,
-            level,
-            WGL2::RGBA as i32,
-            width,
-            height,
-            border,
-            WGL2::RGBA,
-            WGL2::UNSIGNED_BYTE,
-            None,
-        )?;
-        gl.tex_parameteri(
-            WGL2::TEXTURE_2D,
-            WGL2::TEXTURE_MIN_FILTER,
-            WGL2::LINEAR as i32,
-        );
-        gl.tex_parameteri(
-            WGL2::TEXTURE_2D,
-            WGL2::TEXTURE_WRAP_S,
-            WGL2::CLAMP_TO_EDGE as i32,
-        );
-        gl.tex_parameteri(
-            WGL2::TEXTURE_2D,
-            WGL2::TEXTURE_WRAP_T,
-            WGL2::CLAMP_TO_EDGE as i32,
-        );
-        let fb = gl.create_framebuffer();
-        gl.bind_framebuffer(WGL2::FRAMEBUFFER, fb.as_ref());
-        gl.framebuffer_texture_2d(
-            WGL2::FRAMEBUFFER,
-            WGL2::COLOR_ATTACHMENT0,
-            WGL2::TEXTURE_2D,
-            texture.as_ref(),
-            level,
-        );
-        gl.viewport(0, 0, width, height);
-        self.clear(1.0, 1.0, 1.0, 1.0);
-        gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
-        gl.bind_texture(WGL2::TEXTURE_2D, None);
-        self.canvas_fb = fb;
-        self.canvas_tex = texture;
+        let composite = RenderTarget::new(gl, width, height)?;
+        let composite_back = RenderTarget::new(gl, width, height)?;
+        self.composite = Some(composite);
+        self.composite_back = Some(composite_back);
+        self.selection = Selection::new(width as usize, height as usize);
+        self.selection_target = Some(RenderTarget::new(gl, width, height)?);
+
+        let background = self.background_layer(width, height)?;
+        self.layers.insert_above_active(background);
         Ok(())
     }
 
+    // an opaque white layer, the starting point of every new document
+    fn background_layer(&mut self, width: i32, height: i32) -> Result<Layer, JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let target = RenderTarget::new(gl, width, height)?;
+        target.clear(gl, 1.0, 1.0, 1.0, 1.0);
+        Ok(Layer::new(
+            self.layers.next_id(),
+            String::from("Background"),
+            target,
+        ))
+    }
+
+    // device pixels per CSS pixel
+    fn pixel_ratio(&self) -> f64 {
+        web_sys::window()
+            .map(|window| window.device_pixel_ratio())
+            .filter(|ratio| *ratio > 0.0)
+            .unwrap_or(1.0)
+    }
+
     fn resize_canvas(&self) {
         let gl = self.gl.as_ref().unwrap();
         let canvas = self.canvas.as_ref().unwrap();
-        let rect = canvas.get_bounding_client_rect();
-        let client_width = rect.width() as u32;
-        let client_height = rect.height() as u32;
+        // size the drawing buffer in device pixels so it isn't upscaled (blurry)
+        // on HiDPI displays. The content box excludes the canvas border
+        let ratio = self.pixel_ratio();
+        let client_width = (canvas.client_width() as f64 * ratio).round() as u32;
+        let client_height = (canvas.client_height() as f64 * ratio).round() as u32;
 
         let width = canvas.width();
         let height = canvas.height();
@@ -160,71 +1646,341 @@ impl Engine {
         gl.viewport(0, 0, client_width as i32, client_height as i32);
     }
 
-    fn draw_tri(&self, len: f32, pos_x: f32, pos_y: f32) -> Result<(), JsValue> {
-        let gl = self.gl.as_ref().unwrap();
-
-        let d = len / 3.0f32.sqrt();
+    fn draw_dab_quads(&self, dabs: &[Dab]) -> Result<(), JsValue> {
+        self.draw_brush_triangles(&Self::dab_vertices(dabs))
+    }
 
-        // triangle vertices
-        let vertices: [f32; 9] = [
-            // left
-            -d + pos_x,
-            -d + pos_y,
-            0.0,
-            // right
-            d + pos_x,
-            -d + pos_y,
-            0.0,
-            //top
-            pos_x,
-            d + pos_y,
-            0.0,
+    fn dab_vertices(dabs: &[Dab]) -> Vec<f32> {
+        // one quad per dab: position (canvas pixels), dab coords, opacity
+        let corners: [(f32, f32); 6] = [
+            (-1.0, 1.0),
+            (-1.0, -1.0),
+            (1.0, -1.0),
+            (-1.0, 1.0),
+            (1.0, -1.0),
+            (1.0, 1.0),
         ];
+        let mut vertices = Vec::with_capacity(dabs.len() * corners.len() * 5);
+        for dab in dabs {
+            let radius = dab.size / 2.0;
+            for (u, v) in corners.iter() {
+                vertices.extend_from_slice(&[
+                    dab.x + u * radius,
+                    dab.y + v * radius,
+                    *u,
+                    *v,
+                    dab.opacity,
+                ]);
+            }
+        }
+        vertices
+    }
 
+    // draws triangles with the brush program into the active layer. Each vertex
+    // is a position (document pixels), dab coords and an opacity
+    fn draw_brush_triangles(&self, vertices: &[f32]) -> Result<(), JsValue> {
+        let gl = self.gl.as_ref().unwrap();
         let buffer = gl.create_buffer().ok_or("Failed to create buffer")?;
         gl.bind_buffer(WGL2::ARRAY_BUFFER, Some(&buffer));
 
         unsafe {
-            let vert_array = Float32Array::view(&vertices);
+            let vert_array = Float32Array::view(vertices);
 
             gl.buffer_data_with_array_buffer_view(
                 WGL2::ARRAY_BUFFER,
                 &vert_array,
-                WGL2::STATIC_DRAW,
+                WGL2::STREAM_DRAW,
             );
         }
 
-        gl.vertex_attrib_pointer_with_i32(0, 3, WGL2::FLOAT, false, 0, 0);
+        let stride = 5 * 4; /* sizeof float */
+        gl.vertex_attrib_pointer_with_i32(0, 2, WGL2::FLOAT, false, stride, 0);
         gl.enable_vertex_attrib_array(0);
+        gl.vertex_attrib_pointer_with_i32(1, 2, WGL2::FLOAT, false, stride, 2 * 4);
+        gl.enable_vertex_attrib_array(1);
+        gl.vertex_attrib_pointer_with_i32(2, 1, WGL2::FLOAT, false, stride, 4 * 4);
+        gl.enable_vertex_attrib_array(2);
 
-        let program = self.tri_program.as_ref();
+        let program = self.brush_program.as_ref();
         gl.use_program(program);
 
+        let target = &self.layers.active().unwrap().target;
+        let uniform_loc = gl.get_uniform_location(program.unwrap(), "resolution");
+        gl.uniform2f(
+            uniform_loc.as_ref(),
+            target.width as f32,
+            target.height as f32,
+        );
+        self.bind_selection(program.unwrap(), "use_selection");
+        let brush = self.stroke_brush();
         let uniform_loc = gl.get_uniform_location(program.unwrap(), "color");
-        gl.uniform4fv_with_f32_array(uniform_loc.as_ref(), &self.brush.color);
-        // draw to canvas framebuffer
-        gl.bind_framebuffer(WGL2::FRAMEBUFFER, self.canvas_fb.as_ref());
+        gl.uniform4fv_with_f32_array(uniform_loc.as_ref(), &brush.color);
+        let uniform_loc = gl.get_uniform_location(program.unwrap(), "hardness");
+        gl.uniform1f(uniform_loc.as_ref(), brush.hardness);
+        // draw to the active layer
+        target.bind(gl);
+
+        if self.erasing {
+            // scale the layer (color and alpha alike, as it is premultiplied)
+            // by one minus the dab's coverage
+            gl.blend_func(WGL2::ZERO, WGL2::ONE_MINUS_SRC_ALPHA);
+        }
+        gl.draw_arrays(WGL2::TRIANGLES, 0, (vertices.len() / 5) as i32);
+        if self.erasing {
+            gl.blend_func(WGL2::ONE, WGL2::ONE_MINUS_SRC_ALPHA);
+        }
+        self.unbind_selection();
+        gl.disable_vertex_attrib_array(2);
+        gl.delete_buffer(Some(&buffer));
+        gl.flush();
+        Ok(())
+    }
+
+    // replaces the previous preview of the shape in progress with its current outline and fill
+    fn draw_shape(&mut self) -> Result<(), JsValue> {
+        if self.shape.is_none() {
+            return Ok(());
+        }
+        self.restore_edit_rect();
+        let shape = self.shape.as_ref().unwrap();
+        let options = self.shape_options;
+        let mut vertices = Vec::new();
+        if options.fill {
+            // dab coords of zero keep the whole triangle at full coverage
+            for (x, y) in shape.fill_triangles() {
+                vertices.extend_from_slice(&[x, y, 0.0, 0.0, self.brush.opacity]);
+            }
+        }
+        // lines have no inside, so they are always stroked
+        if options.outline || shape.kind == ShapeKind::Line {
+            vertices.extend(Self::dab_vertices(&shape.outline_dabs(&self.brush)));
+        }
+        let bounds = shape.bounds(&self.brush);
+        if !vertices.is_empty() {
+            self.draw_brush_triangles(&vertices)?;
+        }
+        self.mark_dirty(&bounds);
+        self.redraw();
+        Ok(())
+    }
+
+    // replaces the previous preview of the gradient being dragged out with its current line
+    fn draw_gradient_line(&mut self) -> Result<(), JsValue> {
+        let (start, end) = match self.gradient_line {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        self.restore_edit_rect();
+        // a click without a drag has no direction to spread the stops along
+        if start == end {
+            self.redraw();
+            return Ok(());
+        }
+        let gl = self.gl.as_ref().unwrap();
+        let target = &self.layers.active().unwrap().target;
+        let program = self.gradient_program.as_ref().unwrap();
+        gl.use_program(Some(program));
+        self.set_quad_transform(program, &Affine::identity());
+        let uniform_loc = gl.get_uniform_location(program, "start");
+        gl.uniform2f(uniform_loc.as_ref(), start.0, start.1);
+        let uniform_loc = gl.get_uniform_location(program, "end");
+        gl.uniform2f(uniform_loc.as_ref(), end.0, end.1);
+        let uniform_loc = gl.get_uniform_location(program, "target_height");
+        gl.uniform1f(uniform_loc.as_ref(), target.height as f32);
+        let uniform_loc = gl.get_uniform_location(program, "shape");
+        gl.uniform1i(uniform_loc.as_ref(), self.gradient.shape as i32);
+        let uniform_loc = gl.get_uniform_location(program, "stop_count");
+        gl.uniform1i(uniform_loc.as_ref(), self.gradient.stops.len() as i32);
+        let uniform_loc = gl.get_uniform_location(program, "stop_positions");
+        gl.uniform1fv_with_f32_array(uniform_loc.as_ref(), &self.gradient.positions());
+        let uniform_loc = gl.get_uniform_location(program, "stop_colors");
+        gl.uniform4fv_with_f32_array(uniform_loc.as_ref(), &self.gradient.premultiplied_colors());
+        let uniform_loc = gl.get_uniform_location(program, "dither");
+        gl.uniform1i(uniform_loc.as_ref(), self.gradient.dither as i32);
+        self.bind_selection(program, "use_selection");
+        target.bind(gl);
+        let result = self.draw_quad();
+        self.unbind_selection();
+        result?;
+        gl.flush();
+        let rect = if self.selection.active {
+            self.selection.mask.bounds()
+        } else {
+            target.rect()
+        };
+        self.mark_dirty(&rect);
+        self.redraw();
+        Ok(())
+    }
+
+    // composites visible layers bottom to top, returning the target holding the result
+    fn composite_layers(&self) -> Result<&RenderTarget, JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let mut src = self.composite_back.as_ref().unwrap();
+        let mut dst = self.composite.as_ref().unwrap();
+        dst.clear(gl, 0.0, 0.0, 0.0, 0.0);
+
+        let program = self.quad_program.as_ref().unwrap();
+        gl.use_program(Some(program));
+        let uniform_loc = gl.get_uniform_location(program, "tex");
+        gl.uniform1i(uniform_loc.as_ref(), 0);
+        let uniform_loc = gl.get_uniform_location(program, "backdrop");
+        gl.uniform1i(uniform_loc.as_ref(), 1);
+        self.set_quad_transform(program, &Affine::identity());
+
+        // the shader does its own blending against the backdrop
+        gl.disable(WGL2::BLEND);
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            std::mem::swap(&mut src, &mut dst);
+            dst.bind(gl);
+            let uniform_loc = gl.get_uniform_location(program, "opacity");
+            gl.uniform1f(uniform_loc.as_ref(), layer.opacity);
+            let uniform_loc = gl.get_uniform_location(program, "blend_mode");
+            gl.uniform1i(uniform_loc.as_ref(), layer.blend_mode as i32);
+            gl.active_texture(WGL2::TEXTURE1);
+            gl.bind_texture(WGL2::TEXTURE_2D, Some(&src.texture));
+            gl.active_texture(WGL2::TEXTURE0);
+            gl.bind_texture(WGL2::TEXTURE_2D, Some(&layer.target.texture));
+            let result = self.draw_quad();
+            gl.active_texture(WGL2::TEXTURE1);
+            gl.bind_texture(WGL2::TEXTURE_2D, None);
+            gl.active_texture(WGL2::TEXTURE0);
+            gl.bind_texture(WGL2::TEXTURE_2D, None);
+            if let Err(err) = result {
+                gl.enable(WGL2::BLEND);
+                return Err(err);
+            }
+        }
+        gl.enable(WGL2::BLEND);
+        self.composite_in_back
+            .set(std::ptr::eq(dst, self.composite_back.as_ref().unwrap()));
+        Ok(dst)
+    }
+
+    fn draw_canvas(&self) -> Result<(), JsValue> {
+        let composite = self.composite_layers()?;
+        self.present(composite)
+    }
 
+    // draws the `composite` of the document to the canvas, with the view's
+    // transform and the selection's marching ants
+    fn present(&self, composite: &RenderTarget) -> Result<(), JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        // draw to default framebuffer, filling around the document
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
         let canvas = self.canvas.as_ref().unwrap();
         let width = canvas.width() as i32;
         let height = canvas.height() as i32;
         gl.viewport(0, 0, width, height);
+        self.clear(0.5, 0.5, 0.5, 1.0);
 
-        gl.draw_arrays(WGL2::TRIANGLES, 0, 3);
+        let program = self.canvas_program.as_ref().unwrap();
+        gl.use_program(Some(program));
+        let (document, canvas) = self.view_sizes();
+        self.set_quad_transform(program, &self.view.ndc_transform(document, canvas));
+        let uniform_loc = gl.get_uniform_location(program, "checkerboard");
+        gl.uniform1i(uniform_loc.as_ref(), 1);
+        self.bind_selection(program, "show_selection");
+        let phase_loc = gl.get_uniform_location(program, "ants_phase");
+        gl.uniform1f(phase_loc.as_ref(), self.ants_phase);
+        gl.bind_texture(WGL2::TEXTURE_2D, Some(&composite.texture));
+        // show crisp pixels when zoomed in
+        let mag_filter = if self.view.zoom >= 2.0 {
+            WGL2::NEAREST
+        } else {
+            WGL2::LINEAR
+        };
+        gl.tex_parameteri(
+            WGL2::TEXTURE_2D,
+            WGL2::TEXTURE_MAG_FILTER,
+            mag_filter as i32,
+        );
+        let result = self.draw_quad();
+        gl.uniform1i(uniform_loc.as_ref(), 0);
+        self.unbind_selection();
+        gl.tex_parameteri(
+            WGL2::TEXTURE_2D,
+            WGL2::TEXTURE_MAG_FILTER,
+            WGL2::LINEAR as i32,
+        );
+        gl.bind_texture(WGL2::TEXTURE_2D, None);
+        result?;
         gl.flush();
         Ok(())
     }
 
-    fn draw_canvas(&self) -> Result<(), JsValue> {
+    // binds the selection mask to texture unit 1 for `program`, setting the
+    // bool uniform `flag` to whether there is a selection
+    fn bind_selection(&self, program: &WebGlProgram, flag: &str) {
+        let gl = self.gl.as_ref().unwrap();
+        let uniform_loc = gl.get_uniform_location(program, flag);
+        gl.uniform1i(uniform_loc.as_ref(), self.selection.active as i32);
+        let uniform_loc = gl.get_uniform_location(program, "selection");
+        gl.uniform1i(uniform_loc.as_ref(), 1);
+        gl.active_texture(WGL2::TEXTURE1);
+        gl.bind_texture(
+            WGL2::TEXTURE_2D,
+            self.selection_target.as_ref().map(|target| &target.texture),
+        );
+        gl.active_texture(WGL2::TEXTURE0);
+    }
+
+    fn unbind_selection(&self) {
+        let gl = self.gl.as_ref().unwrap();
+        gl.active_texture(WGL2::TEXTURE1);
+        gl.bind_texture(WGL2::TEXTURE_2D, None);
+        gl.active_texture(WGL2::TEXTURE0);
+    }
+
+    // draws `texture` into the document rect at `x, y` of the bound `target`
+    fn draw_texture_rect(
+        &self,
+        texture: &WebGlTexture,
+        target: &RenderTarget,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) -> Result<(), JsValue> {
+        let gl = self.gl.as_ref().unwrap();
+        let (target_width, target_height) = (target.width as f32, target.height as f32);
+        let left = 2.0 * x / target_width - 1.0;
+        let right = 2.0 * (x + width) / target_width - 1.0;
+        let top = 1.0 - 2.0 * y / target_height;
+        let bottom = 1.0 - 2.0 * (y + height) / target_height;
+
+        let program = self.canvas_program.as_ref().unwrap();
+        gl.use_program(Some(program));
+        self.set_quad_transform(program, &Affine::identity());
+        gl.bind_texture(WGL2::TEXTURE_2D, Some(texture));
+        let result = self.draw_quad_ndc(left, bottom, right, top);
+        gl.bind_texture(WGL2::TEXTURE_2D, None);
+        result
+    }
+
+    // sets the `transform` uniform of a program using the quad vertex shader
+    fn set_quad_transform(&self, program: &WebGlProgram, transform: &Affine) {
+        let gl = self.gl.as_ref().unwrap();
+        let uniform_loc = gl.get_uniform_location(program, "transform");
+        gl.uniform_matrix3fv_with_f32_array(uniform_loc.as_ref(), false, &transform.mat3());
+    }
+
+    // draws a quad over the whole bound framebuffer with the program in use
+    fn draw_quad(&self) -> Result<(), JsValue> {
+        self.draw_quad_ndc(-1.0, -1.0, 1.0, 1.0)
+    }
+
+    // draws a quad covering the given NDC bounds with the program in use
+    fn draw_quad_ndc(&self, left: f32, bottom: f32, right: f32, top: f32) -> Result<(), JsValue> {
         let gl = self.gl.as_ref().unwrap();
 
         let vertices: [f32; 24] = [
-            -1.0, 1.0, 0.0, 1.0, //
-            -1.0, -1.0, 0.0, 0.0, //
-            1.0, -1.0, 1.0, 0.0, //
-            -1.0, 1.0, 0.0, 1.0, //
-            1.0, -1.0, 1.0, 0.0, //
-            1.0, 1.0, 1.0, 1.0,
+            left, top, 0.0, 1.0, //
+            left, bottom, 0.0, 0.0, //
+            right, bottom, 1.0, 0.0, //
+            left, top, 0.0, 1.0, //
+            right, bottom, 1.0, 0.0, //
+            right, top, 1.0, 1.0,
         ];
 
         let vert_buffer = gl.create_buffer().ok_or("Failed to create buffer")?;
@@ -252,74 +2008,688 @@ impl Engine {
         gl.vertex_attrib_pointer_with_i32(1, 2, WGL2::FLOAT, false, 4 * 4, 2 * 4);
         gl.enable_vertex_attrib_array(1);
 
-        let program = self.quad_program.as_ref();
-        gl.use_program(program);
+        gl.draw_arrays(WGL2::TRIANGLES, 0, 6);
+        gl.delete_buffer(Some(&vert_buffer));
+        Ok(())
+    }
 
-        // draw to default framebuffer
-        gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
-        self.clear(0.0, 0.0, 0.0, 0.0);
+    fn redraw(&self) {
+        match self.draw_canvas() {
+            Ok(_) => {}
+            Err(_) => {
+                console::log_1(&"engine.draw_canvas error".into());
+            }
+        }
+    }
+
+    // redraws the canvas from the last composite, for when only the marching
+    // ants have moved
+    fn redraw_ants(&self) {
+        let composite = if self.composite_in_back.get() {
+            self.composite_back.as_ref()
+        } else {
+            self.composite.as_ref()
+        };
+        if self.present(composite.unwrap()).is_err() {
+            console::log_1(&"engine.present error".into());
+        }
+    }
+
+    // the event's position in canvas (device) pixels. web-sys truncates
+    // offsetX/Y to integers, so the fractional values pens report are read
+    // directly, then scaled from CSS pixels to the drawing buffer
+    fn canvas_position(&self, event: &MouseEvent) -> (f32, f32) {
+        let offset = |name: &str| {
+            js_sys::Reflect::get(event, &JsValue::from_str(name))
+                .ok()
+                .and_then(|value| value.as_f64())
+                .unwrap_or(0.0)
+        };
+        // the buffer is the CSS size times devicePixelRatio, up to rounding
         let canvas = self.canvas.as_ref().unwrap();
-        let width = canvas.width() as i32;
-        let height = canvas.height() as i32;
-        gl.viewport(0, 0, width, height);
+        let scale = |buffer: u32, css: i32| {
+            if css > 0 {
+                buffer as f64 / css as f64
+            } else {
+                1.0
+            }
+        };
+        let x = offset("offsetX") * scale(canvas.width(), canvas.client_width());
+        let y = offset("offsetY") * scale(canvas.height(), canvas.client_height());
+        (x as f32, y as f32)
+    }
 
-        gl.bind_texture(WGL2::TEXTURE_2D, self.canvas_tex.as_ref());
-        gl.draw_arrays(WGL2::TRIANGLES, 0, 6);
-        gl.flush();
+    // the event's position mapped through the view into document pixels
+    fn pointer_sample(&self, event: &PointerEvent) -> PointerSample {
+        let (canvas_x, canvas_y) = self.canvas_position(event);
+        let (document, canvas) = self.view_sizes();
+        let (x, y) = self
+            .view
+            .canvas_to_document(document, canvas)
+            .apply(canvas_x, canvas_y);
+        PointerSample::from_event(event, x, y)
+    }
+
+    fn pointer_down(&mut self, event: &PointerEvent) {
+        // middle button drags the view: pan, or rotate with shift
+        if event.button() == 1 && self.view_drag.is_none() && !self.pointer_state.pressed() {
+            let (x, y) = self.canvas_position(event);
+            let _ = self
+                .canvas
+                .as_ref()
+                .unwrap()
+                .set_pointer_capture(event.pointer_id());
+            self.view_drag = Some(ViewDrag {
+                pointer_id: event.pointer_id(),
+                gesture: if event.shift_key() {
+                    ViewGesture::Rotate
+                } else {
+                    ViewGesture::Pan
+                },
+                last_x: x,
+                last_y: y,
+            });
+            return;
+        }
+        // a stylus eraser end reports button 5 and sets bit 32 of buttons
+        let eraser_end = event.buttons() & 32 != 0;
+        // only primary buttons paint; ignore extra fingers while a stroke is active
+        if (event.button() != 0 && !eraser_end) || !self.pointer_state.capture(event.pointer_id()) {
+            return;
+        }
+        // keep receiving events if the pointer leaves the canvas mid-stroke
+        let _ = self
+            .canvas
+            .as_ref()
+            .unwrap()
+            .set_pointer_capture(event.pointer_id());
+        let sample = self.pointer_sample(event);
+        self.pointer_state.set_sample(sample);
+
+        // picking and selecting work on hidden and locked layers too
+        if self.tool == Tool::Eyedropper && !eraser_end {
+            self.picking = true;
+            self.pick_at(event);
+            return;
+        }
+        if self.tool == Tool::MagicWand && !eraser_end {
+            self.pointer_state.set_pressed(false);
+            self.settle_edits();
+            if self.magic_wand(sample.x, sample.y).is_err() {
+                console::log_1(&"engine.magic_wand error".into());
+            }
+            return;
+        }
+        let selection_kind = if eraser_end {
+            None
+        } else {
+            self.tool.selection_shape()
+        };
+        if let Some(kind) = selection_kind {
+            self.settle_edits();
+            self.start_selection(kind, (sample.x, sample.y), event.shift_key());
+            return;
+        }
+        if !self.layers.active().unwrap().editable() {
+            self.pointer_state.set_pressed(false);
+            return;
+        }
+        if self.tool == Tool::Transform && !eraser_end {
+            self.cancel_shape();
+            if self.grab_floating((sample.x, sample.y)).is_err() {
+                console::log_1(&"engine.grab_floating error".into());
+            }
+            return;
+        }
+        if self.tool == Tool::Gradient && !eraser_end {
+            self.settle_edits();
+            if self.begin_edit().is_err() {
+                console::log_1(&"engine.begin_edit error".into());
+            }
+            self.gradient_line = Some(((sample.x, sample.y), (sample.x, sample.y)));
+            return;
+        }
+        let shape_kind = if eraser_end { None } else { self.tool.shape() };
+        if let Some(kind) = shape_kind {
+            self.shape_pressed = true;
+            if self.shape_down(kind, event, sample.x, sample.y).is_err() {
+                console::log_1(&"engine.shape_down error".into());
+            }
+            return;
+        }
+        if self.tool == Tool::Bucket && !eraser_end {
+            self.pointer_state.set_pressed(false);
+            if self.bucket_fill(sample.x, sample.y).is_err() {
+                console::log_1(&"engine.bucket_fill error".into());
+            }
+            return;
+        }
+        self.settle_edits();
+        if self.begin_edit().is_err() {
+            console::log_1(&"engine.begin_edit error".into());
+        }
+        self.erasing = eraser_end || self.tool == Tool::Eraser;
+        self.stroke = Some(Stroke::new(self.stroke_brush().spacing));
+        self.stabilizer.begin();
+        let samples = self.stabilizer.push(sample);
+        self.add_stroke_points(&samples);
+    }
+
+    fn pointer_move(&mut self, event: &PointerEvent) {
+        if self.drag_view(event) {
+            return;
+        }
+        if self.picking && self.pointer_state.is_captured(event.pointer_id()) {
+            self.pick_at(event);
+            return;
+        }
+        if let Some(floating) = self.floating.as_ref() {
+            if floating.drag.is_some() {
+                if self.pointer_state.is_captured(event.pointer_id()) {
+                    let sample = self.pointer_sample(event);
+                    self.drag_floating((sample.x, sample.y));
+                }
+                return;
+            }
+        }
+        if self.selection_drag.is_some() {
+            if self.pointer_state.is_captured(event.pointer_id()) {
+                let sample = self.pointer_sample(event);
+                self.drag_selection((sample.x, sample.y), event.shift_key());
+            }
+            return;
+        }
+        if let Some((start, _)) = self.gradient_line {
+            if self.pointer_state.is_captured(event.pointer_id()) {
+                let sample = self.pointer_sample(event);
+                // shift keeps the gradient to 45° steps
+                let end = if event.shift_key() {
+                    shape::snap_angle(start, (sample.x, sample.y))
+                } else {
+                    (sample.x, sample.y)
+                };
+                self.gradient_line = Some((start, end));
+                if self.draw_gradient_line().is_err() {
+                    console::log_1(&"engine.draw_gradient_line error".into());
+                }
+            }
+            return;
+        }
+        if let Some(shape) = self.shape.as_ref() {
+            // polygons follow the pointer between clicks too
+            let dragging =
+                self.pointer_state.pressed() && self.pointer_state.is_captured(event.pointer_id());
+            if dragging || shape.kind == ShapeKind::Polygon {
+                let sample = self.pointer_sample(event);
+                self.shape
+                    .as_mut()
+                    .unwrap()
+                    .set_cursor((sample.x, sample.y), event.shift_key());
+                if self.draw_shape().is_err() {
+                    console::log_1(&"engine.draw_shape error".into());
+                }
+            }
+            return;
+        }
+        if !self.pointer_state.pressed() || !self.pointer_state.is_captured(event.pointer_id()) {
+            return;
+        }
+        let sample = self.pointer_sample(event);
+        self.pointer_state.set_sample(sample);
+        let samples = self.stabilizer.push(sample);
+        self.add_stroke_points(&samples);
+    }
+
+    fn pointer_up(&mut self, event: &PointerEvent) {
+        if let Some(drag) = self.view_drag.as_ref() {
+            if drag.pointer_id == event.pointer_id() {
+                self.view_drag = None;
+                return;
+            }
+        }
+        if !self.pointer_state.is_captured(event.pointer_id()) {
+            return;
+        }
+        self.pointer_state.set_pressed(false);
+        if self.picking {
+            self.picking = false;
+            return;
+        }
+        if self.selection_drag.is_some() {
+            self.finish_selection();
+            return;
+        }
+        if let Some(floating) = self.floating.as_mut() {
+            if floating.drag.take().is_some() {
+                return;
+            }
+        }
+        if self.gradient_line.take().is_some() {
+            if self.commit_edit().is_err() {
+                console::log_1(&"engine.commit_edit error".into());
+            }
+            return;
+        }
+        if std::mem::take(&mut self.shape_pressed) {
+            // polygons stay open until they are closed by a click
+            let dragged = matches!(&self.shape, Some(shape) if shape.kind != ShapeKind::Polygon);
+            if dragged && self.finish_shape().is_err() {
+                console::log_1(&"engine.finish_shape error".into());
+            }
+            return;
+        }
+        let samples = self.stabilizer.finish();
+        self.add_stroke_points(&samples);
+        self.stroke = None;
+        if self.commit_edit().is_err() {
+            console::log_1(&"engine.commit_edit error".into());
+        }
+    }
+
+    fn pick_at(&mut self, event: &PointerEvent) {
+        let sample = self.pointer_sample(event);
+        if self.pick_color(sample.x, sample.y).is_err() {
+            console::log_1(&"engine.pick_color error".into());
+        }
+    }
+
+    // the pixels matching the color at document pixel `x, y` under `options`, or
+    // `None` if the point is outside the document
+    fn flood_region(&self, x: f32, y: f32, options: &FillOptions) -> Result<Option<Mask>, JsValue> {
+        let (width, height) = self.get_document_size();
+        if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+            return Ok(None);
+        }
+        let (width, height) = (width as usize, height as usize);
+        // both straight alpha, so tolerance means the same on translucent pixels
+        let sample = if options.sample_merged {
+            self.read_merged_image()?.pixels
+        } else {
+            self.read_layer_image(self.layers.active().unwrap())?.pixels
+        };
+        let mut mask = fill::flood(&sample, width, height, x as usize, y as usize, options);
+        mask.expand(options.expand as usize);
+        if options.antialias {
+            mask.antialias();
+        }
+        Ok(Some(mask))
+    }
+
+    // ends any shape, gradient or transform in progress before something else edits the layers
+    fn settle_edits(&mut self) {
+        self.cancel_shape();
+        self.cancel_gradient();
+        if self.commit_transform(Resampling::Bicubic).is_err() {
+            console::log_1(&"engine.commit_transform error".into());
+        }
+    }
+
+    // redraws the layer as its base with the floating pixels drawn over it
+    fn draw_floating(&mut self, resampling: Resampling) -> Result<(), JsValue> {
+        let floating = match self.floating.as_ref() {
+            Some(floating) => floating,
+            None => return Ok(()),
+        };
+        let gl = self.gl.as_ref().unwrap();
+        let layer = &self.layers.active().unwrap().target;
+        let previous = self.edit_rect.intersect(&layer.rect());
+        layer.copy_rect_from(gl, &floating.base, &previous, previous.x, previous.y);
+        let bounds = floating.bounds().intersect(&layer.rect());
+        let inverse = floating.homography().inverse();
+        if let (false, Some(inverse)) = (bounds.is_empty(), inverse) {
+            let program = self.transform_program.as_ref().unwrap();
+            gl.use_program(Some(program));
+            self.set_quad_transform(program, &Affine::identity());
+            let uniform_loc = gl.get_uniform_location(program, "inverse");
+            gl.uniform_matrix3fv_with_f32_array(uniform_loc.as_ref(), false, &inverse.mat3());
+            let uniform_loc = gl.get_uniform_location(program, "target_height");
+            gl.uniform1f(uniform_loc.as_ref(), layer.height as f32);
+            let uniform_loc = gl.get_uniform_location(program, "kernel");
+            gl.uniform1i(uniform_loc.as_ref(), resampling as i32);
+            layer.bind(gl);
+            let (width, height) = (layer.width as f32, layer.height as f32);
+            gl.bind_texture(WGL2::TEXTURE_2D, Some(&floating.target.texture));
+            let result = self.draw_quad_ndc(
+                2.0 * bounds.x as f32 / width - 1.0,
+                1.0 - 2.0 * (bounds.y + bounds.height) as f32 / height,
+                2.0 * (bounds.x + bounds.width) as f32 / width - 1.0,
+                1.0 - 2.0 * bounds.y as f32 / height,
+            );
+            gl.bind_texture(WGL2::TEXTURE_2D, None);
+            gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
+            result?;
+        }
+        self.mark_dirty(&bounds);
+        self.redraw();
+        Ok(())
+    }
+
+    // starts dragging a handle of the floating pixels, lifting them first if needed
+    fn grab_floating(&mut self, at: (f32, f32)) -> Result<(), JsValue> {
+        if self.floating.is_none() {
+            self.begin_transform()?;
+        }
+        let reach = SNAP_DISTANCE * self.pixel_ratio() as f32 / self.view.zoom;
+        let floating = self.floating.as_mut().unwrap();
+        floating.drag = Some((floating.handle_at(at, reach), at));
+        Ok(())
+    }
+
+    fn drag_floating(&mut self, to: (f32, f32)) {
+        let floating = self.floating.as_mut().unwrap();
+        let (handle, from): (Handle, _) = floating.drag.unwrap();
+        floating.drag_handle(handle, from, to);
+        floating.drag = Some((handle, to));
+        if self.draw_floating(Resampling::Bilinear).is_err() {
+            console::log_1(&"engine.draw_floating error".into());
+        }
+    }
+
+    // uploads `rect` of the selection mask for the shaders
+    fn upload_selection(&self, rect: &Rect) -> Result<(), JsValue> {
+        let rect = rect.intersect(&self.selection.bounds());
+        if rect.is_empty() {
+            return Ok(());
+        }
+        let mut pixels = self.selection.rgba(&rect);
+        codec::flip_rows(&mut pixels, rect.width as usize, rect.height as usize);
+        self.selection_target.as_ref().unwrap().write_pixels(
+            self.gl.as_ref().unwrap(),
+            &rect,
+            &pixels,
+        )
+    }
+
+    fn selection_changed(&mut self) -> Result<(), JsValue> {
+        if self.selection.active {
+            self.upload_selection(&self.selection.bounds())?;
+        }
+        self.redraw();
         Ok(())
     }
 
+    // begins dragging out a selection outline from document point `at`
+    fn start_selection(&mut self, kind: ShapeKind, at: (f32, f32), constrain: bool) {
+        let mut shape = Shape::new(kind, at);
+        shape.set_cursor(at, constrain);
+        let base = self.selection.clone();
+        // outside the outline the result is fixed for the whole drag
+        let everywhere = self.selection.bounds();
+        self.selection.combine(
+            &base,
+            self.selection_mode,
+            &[],
+            &Rect::new(0, 0, 0, 0),
+            &everywhere,
+        );
+        self.selection_drag = Some(SelectionDrag {
+            shape,
+            mode: self.selection_mode,
+            base,
+            bounds: Rect::new(0, 0, 0, 0),
+        });
+        if self.upload_selection(&everywhere).is_err() {
+            console::log_1(&"engine.upload_selection error".into());
+        }
+        self.redraw();
+    }
+
+    fn drag_selection(&mut self, to: (f32, f32), constrain: bool) {
+        let drag = self.selection_drag.as_mut().unwrap();
+        if drag.shape.kind == ShapeKind::Polygon {
+            // lassos follow the pointer freehand
+            drag.shape.set_cursor(to, false);
+            let last = drag.shape.last_point();
+            if (to.0 - last.0).hypot(to.1 - last.1) >= LASSO_SPACING {
+                drag.shape.add_vertex();
+            }
+        } else {
+            drag.shape.set_cursor(to, constrain);
+        }
+        self.update_selection(0.0);
+    }
+
+    fn finish_selection(&mut self) {
+        self.selection_drag.as_mut().unwrap().shape.finish();
+        self.update_selection(self.selection_feather);
+        self.selection_drag = None;
+        self.selection.settle();
+        self.redraw();
+    }
+
+    // combines the dragged outline, feathered by `feather` pixels, into the selection
+    fn update_selection(&mut self, feather: f32) {
+        let drag = self.selection_drag.as_mut().unwrap();
+        let (path, _) = drag.shape.path();
+        let spread = feather.ceil() as i32 + 2;
+        let mut rect = selection::path_bounds(&path);
+        if feather > 0.0 {
+            rect = Rect::new(
+                rect.x - spread,
+                rect.y - spread,
+                rect.width + 2 * spread,
+                rect.height + 2 * spread,
+            );
+        }
+        // pixels past the document's edge can't be selected, but their coverage
+        // still bleeds in when feathering
+        let bounds = self.selection.bounds();
+        let limit = Rect::new(
+            bounds.x - spread,
+            bounds.y - spread,
+            bounds.width + 2 * spread,
+            bounds.height + 2 * spread,
+        );
+        let rect = rect.intersect(&limit);
+        let mut coverage = Mask {
+            width: rect.width.max(0) as usize,
+            height: rect.height.max(0) as usize,
+            coverage: selection::rasterize(&path, &rect),
+        };
+        coverage.blur(feather);
+        // repaint what the last preview covered too, as the outline may have shrunk
+        let region = rect.union(&drag.bounds);
+        self.selection
+            .combine(&drag.base, drag.mode, &coverage.coverage, &rect, &region);
+        drag.bounds = rect;
+        if self.upload_selection(&region).is_err() {
+            console::log_1(&"engine.upload_selection error".into());
+        }
+        self.redraw();
+    }
+
+    // starts a shape, or places or closes a polygon vertex
+    fn shape_down(
+        &mut self,
+        kind: ShapeKind,
+        event: &PointerEvent,
+        x: f32,
+        y: f32,
+    ) -> Result<(), JsValue> {
+        // clicks this close to the first or last vertex close the polygon
+        let reach = SNAP_DISTANCE * self.pixel_ratio() as f32 / self.view.zoom;
+        let shape = match self.shape.as_mut() {
+            Some(shape) => shape,
+            None => {
+                self.begin_edit()?;
+                self.erasing = false;
+                let mut shape = Shape::new(kind, (x, y));
+                shape.set_cursor((x, y), event.shift_key());
+                self.shape = Some(shape);
+                return self.draw_shape();
+            }
+        };
+        shape.set_cursor((x, y), event.shift_key());
+        let near = |(px, py): (f32, f32)| (px - x).hypot(py - y) <= reach;
+        let closes = (shape.vertex_count() > 2 && near(shape.first_point()))
+            || (shape.vertex_count() > 1 && near(shape.last_point()));
+        if closes {
+            return self.finish_shape();
+        }
+        shape.add_vertex();
+        self.draw_shape()
+    }
+
+    // moves the view if the event belongs to a view drag, returning whether it did
+    fn drag_view(&mut self, event: &PointerEvent) -> bool {
+        let (x, y) = self.canvas_position(event);
+        let (document, canvas) = self.view_sizes();
+        let drag = match self.view_drag.as_mut() {
+            Some(drag) if drag.pointer_id == event.pointer_id() => drag,
+            _ => return false,
+        };
+        match drag.gesture {
+            ViewGesture::Pan => self.view.pan_by(x - drag.last_x, y - drag.last_y),
+            ViewGesture::Rotate => {
+                // rotate by the angle swept around the canvas center
+                let center = (canvas.0 / 2.0, canvas.1 / 2.0);
+                let before = (drag.last_y - center.1).atan2(drag.last_x - center.0);
+                let after = (y - center.1).atan2(x - center.0);
+                // the flip mirrors the rotation's direction on screen
+                let sweep = if self.view.flip {
+                    before - after
+                } else {
+                    after - before
+                };
+                let rotation = self.view.rotation + sweep;
+                self.view.rotate_at(rotation, center, document, canvas);
+            }
+        }
+        drag.last_x = x;
+        drag.last_y = y;
+        self.redraw();
+        true
+    }
+
+    // wheel zooms around the cursor, or rotates with alt held
+    fn wheel(&mut self, event: &WheelEvent) {
+        event.prevent_default();
+        let delta = match event.delta_mode() {
+            WheelEvent::DOM_DELTA_LINE => event.delta_y() * 16.0,
+            WheelEvent::DOM_DELTA_PAGE => event.delta_y() * 400.0,
+            _ => event.delta_y(),
+        } as f32;
+        let at = self.canvas_position(event);
+        let (document, canvas) = self.view_sizes();
+        if event.alt_key() {
+            let rotation = self.view.rotation + (delta * 0.25).to_radians();
+            self.view.rotate_at(rotation, at, document, canvas);
+        } else {
+            let zoom = self.view.zoom * (-delta * 0.002).exp();
+            self.view.zoom_at(zoom, at, document, canvas);
+        }
+        self.redraw();
+    }
+
+    // the brush or eraser settings the current stroke uses
+    fn stroke_brush(&self) -> &Brush {
+        if self.erasing {
+            &self.eraser
+        } else {
+            &self.brush
+        }
+    }
+
+    // runs smoothed samples through the stroke and draws the resulting dabs
+    fn add_stroke_points(&mut self, samples: &[PointerSample]) {
+        let brush = if self.erasing {
+            &self.eraser
+        } else {
+            &self.brush
+        };
+        let stroke = match self.stroke.as_mut() {
+            Some(stroke) => stroke,
+            None => return,
+        };
+        let mut dabs = Vec::new();
+        for sample in samples {
+            dabs.extend(stroke.add_point(*sample, |s| brush.dab(s)));
+        }
+        for dab in &dabs {
+            self.mark_dirty(&dab.bounds());
+        }
+        self.draw_dabs(&dabs);
+    }
+
+    fn draw_dabs(&self, dabs: &[Dab]) {
+        if dabs.is_empty() {
+            return;
+        }
+        match self.draw_dab_quads(dabs) {
+            Ok(_) => {}
+            Err(_) => {
+                console::log_1(&"engine.draw_dab_quads error".into());
+            }
+        }
+        self.redraw();
+    }
+
     fn compile_shaders(&mut self) -> Result<(), JsValue> {
         let gl = self.gl.as_ref().unwrap();
 
-        /*
-         Triangle shader
-        */
-        let vert =
-            shader::compile_shader(&gl, WGL2::VERTEX_SHADER, shader::BRUSH_VERTEX_SHADER_SRC)
-                .map_err(|shader_log| {
-                    JsValue::from_str(
-                        format!("Unable to compile vertex shader:\n{}", shader_log).as_str(),
-                    )
-                })?;
-
-        let frag = shader::compile_shader(
-            &gl,
-            WGL2::FRAGMENT_SHADER,
+        // brush dab shader
+        self.brush_program = Some(shader::build_program(
+            gl,
+            shader::BRUSH_VERTEX_SHADER_SRC,
             shader::BRUSH_FRAGMENT_SHADER_SRC,
-        )
-        .map_err(|shader_log| {
-            JsValue::from_str(
-                format!("Unable to compile fragment shader:\n{}", shader_log).as_str(),
-            )
-        })?;
-        let linked = shader::link_program(&gl, &vert, &frag)
-            .map_err(|_| JsValue::from_str("Unable to link shader program"))?;
-        self.tri_program = Some(linked);
-        gl.delete_shader(Some(&vert));
-        gl.delete_shader(Some(&frag));
-        /*
-         Screen quad shader
-        */
-        let vert = shader::compile_shader(&gl, WGL2::VERTEX_SHADER, shader::QUAD_VERTEX_SHADER_SRC)
-            .map_err(|shader_log| {
-                JsValue::from_str(
-                    format!("Unable to compile vertex shader:\n{}", shader_log).as_str(),
-                )
-            })?;
+        )?);
+        // layer compositing shader
+        self.quad_program = Some(shader::build_program(
+            gl,
+            shader::QUAD_VERTEX_SHADER_SRC,
+            shader::QUAD_FRAGMENT_SHADER_SRC,
+        )?);
+        // screen quad shader
+        self.canvas_program = Some(shader::build_program(
+            gl,
+            shader::QUAD_VERTEX_SHADER_SRC,
+            shader::CANVAS_FRAGMENT_SHADER_SRC,
+        )?);
+        // scaling shader
+        self.resample_program = Some(shader::build_program(
+            gl,
+            shader::QUAD_VERTEX_SHADER_SRC,
+            shader::RESAMPLE_FRAGMENT_SHADER_SRC,
+        )?);
+        // free transform shader
+        self.transform_program = Some(shader::build_program(
+            gl,
+            shader::QUAD_VERTEX_SHADER_SRC,
+            shader::TRANSFORM_FRAGMENT_SHADER_SRC,
+        )?);
+        // gradient tool shader
+        self.gradient_program = Some(shader::build_program(
+            gl,
+            shader::QUAD_VERTEX_SHADER_SRC,
+            shader::GRADIENT_FRAGMENT_SHADER_SRC,
+        )?);
+        Ok(())
+    }
 
-        let frag =
-            shader::compile_shader(&gl, WGL2::FRAGMENT_SHADER, shader::QUAD_FRAGMENT_SHADER_SRC)
-                .map_err(|shader_log| {
-                    JsValue::from_str(
-                        format!("Unable to compile fragment shader:\n{}", shader_log).as_str(),
-                    )
-                })?;
-        let linked = shader::link_program(&gl, &vert, &frag)
-            .map_err(|_| JsValue::from_str("Unable to link shader program"))?;
-        self.quad_program = Some(linked);
-        gl.delete_shader(Some(&vert));
-        gl.delete_shader(Some(&frag));
+    // listens for the current devicePixelRatio to stop matching, then re-arms
+    // with the new ratio
+    fn watch_pixel_ratio(this: Rc<RefCell<Self>>) -> Result<(), JsValue> {
+        let window = web_sys::window().ok_or("Could not get window")?;
+        let query = format!("(resolution: {}dppx)", this.borrow().pixel_ratio());
+        let media = match window.match_media(query.as_str())? {
+            Some(media) => media,
+            None => return Ok(()),
+        };
+        let this_clone = this.clone();
+        let media_clone = media.clone();
+        let change = Closure::once_into_js(move |_event: JsValue| {
+            // the closure is freed after this call, so it must not be called again
+            media_clone.set_onchange(None);
+            {
+                let mut this = this_clone.borrow_mut();
+                this.resize_canvas();
+                this.refresh_view();
+            }
+            if Self::watch_pixel_ratio(this_clone).is_err() {
+                console::log_1(&"engine.watch_pixel_ratio error".into());
+            }
+        });
+        media.set_onchange(Some(change.unchecked_ref()));
         Ok(())
     }
 
@@ -329,11 +2699,12 @@ impl Engine {
         */
         // TODO - use event listeners to drop closures
         {
-            // window resize - call gl.viewport
+            // window resize - call gl.viewport and redraw, refitting the document if it was fitted
             let this_clone = this.clone();
-            let resize = Closure::wrap(Box::new(move |event: UiEvent| {
-                // TODO - perspective projection, zoom & pan, etc
-                this_clone.borrow().resize_canvas();
+            let resize = Closure::wrap(Box::new(move |_event: UiEvent| {
+                let mut this = this_clone.borrow_mut();
+                this.resize_canvas();
+                this.refresh_view();
             }) as Box<dyn FnMut(_)>);
             web_sys::window()
                 .unwrap()
@@ -341,93 +2712,96 @@ impl Engine {
                 .map_err(|_| JsValue::from_str("Error adding window onresize listener"))?;
             resize.forget();
         }
+        // devicePixelRatio - resize the drawing buffer when the window moves
+        // to a display with a different pixel density or the page is zoomed
+        Self::watch_pixel_ratio(this.clone())?;
         {
-            // mousemove - draw if pressed
+            // interval - march the selection's ants
             let this_clone = this.clone();
-            let mouse_move = Closure::wrap(Box::new(move |event: MouseEvent| {
-                if !this_clone.borrow().pointer_state.pressed() {
-                    return;
-                }
-                let (width, height) = this_clone.borrow().get_canvas_size();
-                let offset_x = 2.0 * event.offset_x() as f32 / width - 1.0;
-                let offset_y = -(2.0 * event.offset_y() as f32 / height - 1.0);
-                // console::log_4(
-                //     &"Offset: ".into(),
-                //     &offset_x.into(),
-                //     &", ".into(),
-                //     &offset_y.into(),
-                // );
-                match this_clone.borrow().draw_tri(0.1, offset_x, offset_y) {
-                    Ok(_) => {}
-                    Err(_) => {
-                        console::log_1(&"engine.draw_tri error".into());
-                    }
-                }
-                match this_clone.borrow().draw_canvas() {
-                    Ok(_) => {}
-                    Err(_) => {
-                        console::log_1(&"engine.draw_canvas error".into());
-                    }
+            let march = Closure::wrap(Box::new(move || {
+                let mut this = this_clone.borrow_mut();
+                if this.selection.active {
+                    this.ants_phase = (this.ants_phase + 1.0) % 8.0;
+                    this.redraw_ants();
                 }
+            }) as Box<dyn FnMut()>);
+            web_sys::window()
+                .unwrap()
+                .set_interval_with_callback_and_timeout_and_arguments_0(
+                    march.as_ref().unchecked_ref(),
+                    ANTS_INTERVAL,
+                )
+                .map_err(|_| JsValue::from_str("Error starting the marching ants timer"))?;
+            march.forget();
+        }
+        {
+            // wheel - zoom or rotate the view
+            let this_clone = this.clone();
+            let wheel = Closure::wrap(Box::new(move |event: WheelEvent| {
+                this_clone.borrow_mut().wheel(&event);
             }) as Box<dyn FnMut(_)>);
             this.borrow()
                 .canvas
                 .as_ref()
                 .unwrap()
-                .add_event_listener_with_callback("mousemove", mouse_move.as_ref().unchecked_ref())
-                .map_err(|_| JsValue::from_str("Error adding mousemove listener"))?;
-            mouse_move.forget();
+                .add_event_listener_with_callback("wheel", wheel.as_ref().unchecked_ref())
+                .map_err(|_| JsValue::from_str("Error adding wheel listener"))?;
+            wheel.forget();
         }
         {
-            // mousedown - set pressed
+            // pointerdown - capture pointer and start drawing
             let this_clone = this.clone();
-            let mouse_down = Closure::wrap(Box::new(move |event: MouseEvent| {
-                this_clone.borrow_mut().pointer_state.set_pressed(true);
-
-                // draw one triangle at mouse pos
-                let (width, height) = this_clone.borrow().get_canvas_size();
-                let offset_x = 2.0 * event.offset_x() as f32 / width - 1.0;
-                let offset_y = -(2.0 * event.offset_y() as f32 / height - 1.0);
-                // console::log_4(
-                //     &"Offset: ".into(),
-                //     &offset_x.into(),
-                //     &", ".into(),
-                //     &offset_y.into(),
-                // );
-                match this_clone.borrow().draw_tri(0.1, offset_x, offset_y) {
-                    Ok(_) => {}
-                    Err(_) => {
-                        console::log_1(&"engine.draw_tri error".into());
-                    }
-                }
-                match this_clone.borrow().draw_canvas() {
-                    Ok(_) => {}
-                    Err(_) => {
-                        console::log_1(&"engine.draw_canvas error".into());
-                    }
-                }
+            let pointer_down = Closure::wrap(Box::new(move |event: PointerEvent| {
+                this_clone.borrow_mut().pointer_down(&event);
+                Self::report_picked_color(&this_clone);
             }) as Box<dyn FnMut(_)>);
             this.borrow()
                 .canvas
                 .as_ref()
                 .unwrap()
-                .add_event_listener_with_callback("mousedown", mouse_down.as_ref().unchecked_ref())
-                .map_err(|_| JsValue::from_str("Error adding mousedown listener"))?;
-            mouse_down.forget();
+                .add_event_listener_with_callback(
+                    "pointerdown",
+                    pointer_down.as_ref().unchecked_ref(),
+                )
+                .map_err(|_| JsValue::from_str("Error adding pointerdown listener"))?;
+            pointer_down.forget();
         }
         {
-            // mouseup - unset pressed
+            // pointermove - draw if pressed
             let this_clone = this.clone();
-            let mouse_up = Closure::wrap(Box::new(move |event: MouseEvent| {
-                this_clone.borrow_mut().pointer_state.set_pressed(false);
+            let pointer_move = Closure::wrap(Box::new(move |event: PointerEvent| {
+                this_clone.borrow_mut().pointer_move(&event);
+                Self::report_picked_color(&this_clone);
             }) as Box<dyn FnMut(_)>);
             this.borrow()
                 .canvas
                 .as_ref()
                 .unwrap()
-                .add_event_listener_with_callback("mouseup", mouse_up.as_ref().unchecked_ref())
-                .map_err(|_| JsValue::from_str("Error adding mouseup listener"))?;
-            mouse_up.forget();
+                .add_event_listener_with_callback(
+                    "pointermove",
+                    pointer_move.as_ref().unchecked_ref(),
+                )
+                .map_err(|_| JsValue::from_str("Error adding pointermove listener"))?;
+            pointer_move.forget();
+        }
+        {
+            // pointerup & pointercancel - unset pressed
+            let this_clone = this.clone();
+            let pointer_up = Closure::wrap(Box::new(move |event: PointerEvent| {
+                this_clone.borrow_mut().pointer_up(&event);
+            }) as Box<dyn FnMut(_)>);
+            let canvas = this.borrow().canvas.clone().unwrap();
+            for event_type in &["pointerup", "pointercancel"] {
+                canvas
+                    .add_event_listener_with_callback(
+                        event_type,
+                        pointer_up.as_ref().unchecked_ref(),
+                    )
+                    .map_err(|_| {
+                        JsValue::from_str(format!("Error adding {} listener", event_type).as_str())
+                    })?;
+            }
+            pointer_up.forget();
         }
 
         Ok(())
@@ -438,9 +2812,22 @@ impl Drop for Engine {
     fn drop(&mut self) {
         let gl = self.gl.as_ref().unwrap();
         // TODO - safely delete everything
-        gl.delete_program(self.tri_program.as_ref());
+        gl.delete_program(self.brush_program.as_ref());
         gl.delete_program(self.quad_program.as_ref());
-        gl.delete_framebuffer(self.canvas_fb.as_ref());
-        gl.delete_texture(self.canvas_tex.as_ref());
+        gl.delete_program(self.canvas_program.as_ref());
+        gl.delete_program(self.resample_program.as_ref());
+        gl.delete_program(self.transform_program.as_ref());
+        gl.delete_program(self.gradient_program.as_ref());
+        for composite in [&self.composite, &self.composite_back].iter() {
+            if let Some(composite) = composite.as_ref() {
+                composite.delete(gl);
+            }
+        }
+        for layer in self.layers.iter() {
+            layer.target.delete(gl);
+        }
+        if let Some(backup) = self.edit_backup.as_ref() {
+            backup.delete(gl);
+        }
     }
 }
@@ -0,0 +1,39 @@
+/// Settings for the eyedropper tool.
+#[derive(Clone, Copy, Debug)]
+pub struct EyedropperOptions {
+    /// Width of the square of pixels averaged around the pointer; 1 samples a single pixel.
+    pub size: u32,
+    /// Sample the composited document instead of the active layer.
+    pub sample_merged: bool,
+}
+
+impl Default for EyedropperOptions {
+    fn default() -> Self {
+        Self {
+            size: 1,
+            sample_merged: true,
+        }
+    }
+}
+
+/// Averages premultiplied RGBA8 `pixels` into a straight-alpha color, weighting
+/// each pixel by its alpha. Returns `None` if every pixel is transparent.
+pub fn average(pixels: &[u8]) -> Option<[f32; 4]> {
+    let count = pixels.len() / 4;
+    let mut sum = [0u64; 4];
+    for px in pixels.chunks_exact(4) {
+        for (total, channel) in sum.iter_mut().zip(px) {
+            *total += *channel as u64;
+        }
+    }
+    if sum[3] == 0 {
+        return None;
+    }
+    let alpha = sum[3] as f32;
+    Some([
+        (sum[0] as f32 / alpha).min(1.0),
+        (sum[1] as f32 / alpha).min(1.0),
+        (sum[2] as f32 / alpha).min(1.0),
+        alpha / (count as f32 * 255.0),
+    ])
+}
@@ -0,0 +1,328 @@
+use super::texture::Rect;
+
+/// The most pixels a fill region can be grown by.
+pub const MAX_EXPAND: u32 = 100;
+
+/// Settings for the bucket tool and magic wand.
+#[derive(Clone, Copy, Debug)]
+pub struct FillOptions {
+    /// Largest per-channel difference from the seed color that still matches, in `[0, 1]`.
+    pub tolerance: f32,
+    /// Fill only the region connected to the seed, rather than every matching pixel.
+    pub contiguous: bool,
+    /// Match against the composited document instead of the active layer.
+    pub sample_merged: bool,
+    /// Grow the filled region by this many pixels, e.g. to tuck under line art.
+    /// At most `MAX_EXPAND`.
+    pub expand: u32,
+    /// Soften the outer edge of the region by a pixel.
+    pub antialias: bool,
+}
+
+impl Default for FillOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.1,
+            contiguous: true,
+            sample_merged: false,
+            expand: 0,
+            antialias: true,
+        }
+    }
+}
+
+/// A coverage mask over a `width` x `height` image, one byte per pixel.
+#[derive(Clone)]
+pub struct Mask {
+    pub width: usize,
+    pub height: usize,
+    pub coverage: Vec<u8>,
+}
+
+impl Mask {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            coverage: vec![0; width * height],
+        }
+    }
+
+    /// The smallest rect holding every covered pixel.
+    pub fn bounds(&self) -> Rect {
+        let (mut min_x, mut min_y) = (self.width, self.height);
+        let (mut max_x, mut max_y) = (0, 0);
+        for (y, row) in self.coverage.chunks_exact(self.width).enumerate() {
+            let first = match row.iter().position(|c| *c > 0) {
+                Some(x) => x,
+                None => continue,
+            };
+            let last = row.iter().rposition(|c| *c > 0).unwrap();
+            min_x = min_x.min(first);
+            max_x = max_x.max(last + 1);
+            min_y = min_y.min(y);
+            max_y = y + 1;
+        }
+        if max_x == 0 {
+            return Rect::new(0, 0, 0, 0);
+        }
+        Rect::new(
+            min_x as i32,
+            min_y as i32,
+            (max_x - min_x) as i32,
+            (max_y - min_y) as i32,
+        )
+    }
+
+    /// Grows covered pixels by `radius` with a square max filter.
+    pub fn expand(&mut self, radius: usize) {
+        if radius == 0 {
+            return;
+        }
+        let (width, height) = (self.width, self.height);
+        let mut scratch = MaxScratch::default();
+        // separable: rows, then columns
+        for y in 0..height {
+            max_filter(
+                &mut self.coverage,
+                y * width,
+                1,
+                width,
+                radius,
+                &mut scratch,
+            );
+        }
+        for x in 0..width {
+            max_filter(&mut self.coverage, x, width, height, radius, &mut scratch);
+        }
+    }
+
+    /// Softens edges with three box blurs, spreading them over about `radius` pixels.
+    pub fn blur(&mut self, radius: f32) {
+        let box_radius = (radius / 3.0).round() as usize;
+        if box_radius == 0 {
+            return;
+        }
+        let (width, height) = (self.width, self.height);
+        let mut line = Vec::with_capacity(width.max(height));
+        for _ in 0..3 {
+            for y in 0..height {
+                box_blur(
+                    &mut self.coverage,
+                    y * width,
+                    1,
+                    width,
+                    box_radius,
+                    &mut line,
+                );
+            }
+            for x in 0..width {
+                box_blur(&mut self.coverage, x, width, height, box_radius, &mut line);
+            }
+        }
+    }
+
+    /// Gives uncovered pixels next to the region partial coverage, from the
+    /// share of their 3x3 neighbourhood that is covered. Covered pixels keep
+    /// their coverage so no gap opens against neighbouring line art.
+    pub fn antialias(&mut self) {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let source = self.coverage.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                if source[index] == 255 {
+                    continue;
+                }
+                let mut sum = 0u32;
+                for ny in (y - 1).max(0)..(y + 2).min(height) {
+                    for nx in (x - 1).max(0)..(x + 2).min(width) {
+                        sum += source[(ny * width + nx) as usize] as u32;
+                    }
+                }
+                self.coverage[index] = self.coverage[index].max((sum / 9) as u8);
+            }
+        }
+    }
+}
+
+// box-blurs the `len` values starting at `start`, `stride` apart, treating
+// everything past the ends as uncovered
+fn box_blur(
+    values: &mut [u8],
+    start: usize,
+    stride: usize,
+    len: usize,
+    radius: usize,
+    line: &mut Vec<u32>,
+) {
+    line.clear();
+    line.extend((0..len).map(|i| values[start + i * stride] as u32));
+    let window = (2 * radius + 1) as u32;
+    // running sum of the window around `i`
+    let mut sum: u32 = line[..radius.min(len)].iter().sum();
+    for i in 0..len {
+        if i + radius < len {
+            sum += line[i + radius];
+        }
+        values[start + i * stride] = ((sum + window / 2) / window) as u8;
+        if i >= radius {
+            sum -= line[i - radius];
+        }
+    }
+}
+
+// buffers reused across the lines of a max filter
+#[derive(Default)]
+struct MaxScratch {
+    line: Vec<u8>,
+    forward: Vec<u8>,
+    backward: Vec<u8>,
+}
+
+// max-filters the `len` values starting at `start`, `stride` apart, over
+// `radius` values either side, treating everything past the ends as uncovered.
+// After van Herk and Gil-Werman: running maxima forwards and backwards through
+// blocks of the window's size give any window's maximum from two lookups, so
+// the cost doesn't grow with the radius
+fn max_filter(
+    values: &mut [u8],
+    start: usize,
+    stride: usize,
+    len: usize,
+    radius: usize,
+    scratch: &mut MaxScratch,
+) {
+    let window = 2 * radius + 1;
+    let MaxScratch {
+        line,
+        forward,
+        backward,
+    } = scratch;
+    line.clear();
+    line.resize(radius, 0);
+    line.extend((0..len).map(|i| values[start + i * stride]));
+    line.resize(len + 2 * radius, 0);
+    forward.clear();
+    forward.extend_from_slice(line);
+    backward.clear();
+    backward.extend_from_slice(line);
+    let padded = line.len();
+    for i in 1..padded {
+        if i % window != 0 {
+            forward[i] = forward[i].max(forward[i - 1]);
+        }
+    }
+    for i in (0..padded - 1).rev() {
+        if (i + 1) % window != 0 {
+            backward[i] = backward[i].max(backward[i + 1]);
+        }
+    }
+    for i in 0..len {
+        values[start + i * stride] = backward[i].max(forward[i + window - 1]);
+    }
+}
+
+fn matches(pixels: &[u8], index: usize, seed: &[u8], tolerance: u8) -> bool {
+    pixels[index * 4..index * 4 + 4]
+        .iter()
+        .zip(seed)
+        .all(|(a, b)| (*a as i16 - *b as i16).unsigned_abs() as u8 <= tolerance)
+}
+
+/// Finds the pixels of straight-alpha RGBA8 `pixels` (top row first) that match
+/// the color at `x, y`, either connected to it or anywhere in the image.
+pub fn flood(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    options: &FillOptions,
+) -> Mask {
+    let mut mask = Mask::new(width, height);
+    if x >= width || y >= height {
+        return mask;
+    }
+    let tolerance = (options.tolerance.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let seed_index = (y * width + x) * 4;
+    let seed = [
+        pixels[seed_index],
+        pixels[seed_index + 1],
+        pixels[seed_index + 2],
+        pixels[seed_index + 3],
+    ];
+
+    if !options.contiguous {
+        for (index, coverage) in mask.coverage.iter_mut().enumerate() {
+            if matches(pixels, index, &seed, tolerance) {
+                *coverage = 255;
+            }
+        }
+        return mask;
+    }
+
+    // scanline fill: fill a whole horizontal span, then queue the spans above and below it
+    let fillable = |mask: &Mask, x: usize, y: usize| -> bool {
+        let index = y * width + x;
+        mask.coverage[index] == 0 && matches(pixels, index, &seed, tolerance)
+    };
+    let mut seeds = vec![(x, y)];
+    while let Some((x, y)) = seeds.pop() {
+        if !fillable(&mask, x, y) {
+            continue;
+        }
+        let mut left = x;
+        while left > 0 && fillable(&mask, left - 1, y) {
+            left -= 1;
+        }
+        let mut right = x;
+        while right + 1 < width && fillable(&mask, right + 1, y) {
+            right += 1;
+        }
+        for coverage in &mut mask.coverage[y * width + left..=y * width + right] {
+            *coverage = 255;
+        }
+        for ny in [y.wrapping_sub(1), y + 1].iter().copied() {
+            if ny >= height {
+                continue;
+            }
+            // one seed per run of fillable pixels in the neighbouring row
+            let mut in_run = false;
+            for nx in left..=right {
+                let open = fillable(&mask, nx, ny);
+                if open && !in_run {
+                    seeds.push((nx, ny));
+                }
+                in_run = open;
+            }
+        }
+    }
+    mask
+}
+
+/// Blends straight-alpha `color` over premultiplied RGBA8 `pixels` wherever the
+/// mask covers them. `pixels` holds `rect` of the mask's image, top row first.
+pub fn apply(pixels: &mut [u8], rect: &Rect, mask: &Mask, color: [f32; 4]) {
+    let premultiplied = [
+        color[0] * color[3],
+        color[1] * color[3],
+        color[2] * color[3],
+        color[3],
+    ];
+    for row in 0..rect.height as usize {
+        let mask_row = (rect.y as usize + row) * mask.width + rect.x as usize;
+        for col in 0..rect.width as usize {
+            let coverage = mask.coverage[mask_row + col] as f32 / 255.0;
+            if coverage == 0.0 {
+                continue;
+            }
+            let px = &mut pixels[(row * rect.width as usize + col) * 4..][..4];
+            let alpha = premultiplied[3] * coverage;
+            for (channel, value) in px.iter_mut().zip(premultiplied.iter()) {
+                let blended = value * coverage * 255.0 + *channel as f32 * (1.0 - alpha);
+                *channel = blended.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use wasm_bindgen::prelude::*;
+
+/// The most stops a gradient can have, the size of the shader's stop arrays.
+pub const MAX_STOPS: usize = 16;
+
+/// How the distance along the drag maps onto the gradient.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientShape {
+    /// Bands perpendicular to the drag.
+    Linear,
+    /// Circles around the drag start, reaching the last stop at the drag end.
+    Radial,
+    /// Sweeps once around the drag start, beginning in the drag direction.
+    Conic,
+    /// Linear, mirrored back across the drag start.
+    Reflected,
+    /// Squares around the drag start, turned to the drag direction.
+    Diamond,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GradientStop {
+    /// Where the stop sits along the gradient, in `[0, 1]`.
+    pub position: f32,
+    /// Straight-alpha RGBA in `[0, 1]`.
+    pub color: [f32; 4],
+}
+
+/// Settings for the gradient tool.
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    /// Ordered by position, with at least one stop.
+    pub stops: Vec<GradientStop>,
+    pub shape: GradientShape,
+    /// Adds noise of under a quantization step, so wide gradients don't band.
+    pub dither: bool,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self {
+            stops: vec![
+                GradientStop {
+                    position: 0.0,
+                    color: [0.0, 0.0, 0.0, 1.0],
+                },
+                GradientStop {
+                    position: 1.0,
+                    color: [1.0, 1.0, 1.0, 1.0],
+                },
+            ],
+            shape: GradientShape::Linear,
+            dither: true,
+        }
+    }
+}
+
+impl Gradient {
+    /// Stop positions for the shader, padded to `MAX_STOPS`.
+    pub fn positions(&self) -> Vec<f32> {
+        let mut positions: Vec<f32> = self.stops.iter().map(|stop| stop.position).collect();
+        positions.resize(MAX_STOPS, 1.0);
+        positions
+    }
+
+    /// Premultiplied stop colors for the shader, padded to `MAX_STOPS`. Blending
+    /// premultiplied colors keeps transparent stops from tinting their neighbours.
+    pub fn premultiplied_colors(&self) -> Vec<f32> {
+        let mut colors: Vec<f32> = self
+            .stops
+            .iter()
+            .flat_map(|stop| {
+                let [r, g, b, a] = stop.color;
+                [r * a, g * a, b * a, a]
+            })
+            .collect();
+        colors.resize(MAX_STOPS * 4, 0.0);
+        colors
+    }
+}
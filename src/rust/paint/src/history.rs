@@ -0,0 +1,220 @@
+use super::texture::Rect;
+use std::collections::VecDeque;
+
+// default memory budget for undo and redo entries combined
+pub const DEFAULT_BUDGET: usize = 128 * 1024 * 1024;
+
+/// The pixels of one layer region before and after an edit.
+pub struct PixelEdit {
+    pub layer_id: u32,
+    pub rect: Rect,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+impl PixelEdit {
+    fn size(&self) -> usize {
+        self.before.len() + self.after.len()
+    }
+}
+
+/// Undo and redo stacks of pixel edits, kept under a memory budget by
+/// discarding the oldest undo entries first.
+pub struct History {
+    undo: VecDeque<PixelEdit>,
+    redo: Vec<PixelEdit>,
+    budget: usize,
+    used: usize,
+}
+
+impl History {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            budget,
+            used: 0,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.trim();
+    }
+
+    /// Records a new edit. Anything that could be redone is discarded.
+    pub fn push(&mut self, edit: PixelEdit) {
+        for discarded in self.redo.drain(..) {
+            self.used -= discarded.size();
+        }
+        self.used += edit.size();
+        self.undo.push_back(edit);
+        self.trim();
+    }
+
+    /// The most recent edit, for the caller to revert before calling `undo`.
+    pub fn next_undo(&self) -> Option<&PixelEdit> {
+        self.undo.back()
+    }
+
+    /// Moves the most recent edit to the redo stack, once it has been reverted.
+    pub fn undo(&mut self) {
+        if let Some(edit) = self.undo.pop_back() {
+            self.redo.push(edit);
+        }
+    }
+
+    /// The most recently undone edit, for the caller to reapply before calling `redo`.
+    pub fn next_redo(&self) -> Option<&PixelEdit> {
+        self.redo.last()
+    }
+
+    /// Moves the most recently undone edit back to the undo stack, once it has been reapplied.
+    pub fn redo(&mut self) {
+        if let Some(edit) = self.redo.pop() {
+            self.undo.push_back(edit);
+        }
+    }
+
+    /// Drops every entry that refers to the layer, e.g. after it was deleted.
+    pub fn remove_layer(&mut self, layer_id: u32) {
+        self.undo.retain(|edit| edit.layer_id != layer_id);
+        self.redo.retain(|edit| edit.layer_id != layer_id);
+        self.used = self
+            .undo
+            .iter()
+            .chain(self.redo.iter())
+            .map(PixelEdit::size)
+            .sum();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.used = 0;
+    }
+
+    fn trim(&mut self) {
+        while self.used > self.budget {
+            // oldest undo entries go first, then the redo entries furthest from the present
+            let edit = match self.undo.pop_front() {
+                Some(edit) => edit,
+                None if !self.redo.is_empty() => self.redo.remove(0),
+                None => break,
+            };
+            self.used -= edit.size();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an edit of `size` bytes, split evenly between before and after
+    fn edit(layer_id: u32, size: usize) -> PixelEdit {
+        PixelEdit {
+            layer_id,
+            rect: Rect::new(0, 0, 1, 1),
+            before: vec![0; size / 2],
+            after: vec![1; size - size / 2],
+        }
+    }
+
+    // layer ids of the undo and redo stacks, oldest first
+    fn stacks(history: &History) -> (Vec<u32>, Vec<u32>) {
+        (
+            history.undo.iter().map(|edit| edit.layer_id).collect(),
+            history.redo.iter().map(|edit| edit.layer_id).collect(),
+        )
+    }
+
+    #[test]
+    fn undo_and_redo_move_entries_between_stacks() {
+        let mut history = History::new(1000);
+        history.push(edit(1, 10));
+        history.push(edit(2, 10));
+        assert_eq!(history.next_undo().map(|edit| edit.layer_id), Some(2));
+        history.undo();
+        assert_eq!(stacks(&history), (vec![1], vec![2]));
+        assert_eq!(history.next_redo().map(|edit| edit.layer_id), Some(2));
+        history.undo();
+        assert_eq!(stacks(&history), (vec![], vec![2, 1]));
+        assert!(!history.can_undo());
+        // nothing left to move
+        history.undo();
+        assert_eq!(stacks(&history), (vec![], vec![2, 1]));
+        history.redo();
+        assert_eq!(stacks(&history), (vec![1], vec![2]));
+        assert_eq!(history.used, 20);
+    }
+
+    #[test]
+    fn push_discards_redo_entries() {
+        let mut history = History::new(1000);
+        history.push(edit(1, 10));
+        history.push(edit(2, 20));
+        history.undo();
+        assert_eq!(history.used, 30);
+        history.push(edit(3, 40));
+        assert_eq!(stacks(&history), (vec![1, 3], vec![]));
+        assert!(!history.can_redo());
+        assert_eq!(history.used, 50);
+    }
+
+    #[test]
+    fn trims_oldest_undo_entries_then_redo_entries() {
+        let mut history = History::new(30);
+        history.push(edit(1, 10));
+        history.push(edit(2, 10));
+        history.push(edit(3, 10));
+        history.push(edit(4, 10));
+        assert_eq!(stacks(&history), (vec![2, 3, 4], vec![]));
+        assert_eq!(history.used, 30);
+
+        history.undo();
+        history.undo();
+        assert_eq!(stacks(&history), (vec![2], vec![4, 3]));
+        history.set_budget(20);
+        assert_eq!(stacks(&history), (vec![], vec![4, 3]));
+        // the redo entry furthest from the present goes next
+        history.set_budget(10);
+        assert_eq!(stacks(&history), (vec![], vec![3]));
+        assert_eq!(history.used, 10);
+        history.set_budget(0);
+        assert_eq!(stacks(&history), (vec![], vec![]));
+        assert_eq!(history.used, 0);
+    }
+
+    #[test]
+    fn keeps_an_edit_larger_than_the_budget_out() {
+        let mut history = History::new(10);
+        history.push(edit(1, 20));
+        assert!(!history.can_undo());
+        assert_eq!(history.used, 0);
+    }
+
+    #[test]
+    fn remove_layer_recounts_used_memory() {
+        let mut history = History::new(1000);
+        history.push(edit(1, 10));
+        history.push(edit(2, 20));
+        history.push(edit(1, 30));
+        history.push(edit(2, 40));
+        history.undo();
+        history.remove_layer(2);
+        assert_eq!(stacks(&history), (vec![1, 1], vec![]));
+        assert_eq!(history.used, 40);
+        history.clear();
+        assert_eq!(stacks(&history), (vec![], vec![]));
+        assert_eq!(history.used, 0);
+    }
+}
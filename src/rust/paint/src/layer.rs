@@ -0,0 +1,211 @@
+use super::texture::RenderTarget;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Normal = 0,
+    Multiply = 1,
+    Screen = 2,
+    Overlay = 3,
+    Darken = 4,
+    Lighten = 5,
+    ColorDodge = 6,
+    ColorBurn = 7,
+    HardLight = 8,
+    SoftLight = 9,
+    Difference = 10,
+    Exclusion = 11,
+    Hue = 12,
+    Saturation = 13,
+    Color = 14,
+    Luminosity = 15,
+}
+
+impl BlendMode {
+    pub fn from_u32(mode: u32) -> Option<BlendMode> {
+        let mode = match mode {
+            0 => BlendMode::Normal,
+            1 => BlendMode::Multiply,
+            2 => BlendMode::Screen,
+            3 => BlendMode::Overlay,
+            4 => BlendMode::Darken,
+            5 => BlendMode::Lighten,
+            6 => BlendMode::ColorDodge,
+            7 => BlendMode::ColorBurn,
+            8 => BlendMode::HardLight,
+            9 => BlendMode::SoftLight,
+            10 => BlendMode::Difference,
+            11 => BlendMode::Exclusion,
+            12 => BlendMode::Hue,
+            13 => BlendMode::Saturation,
+            14 => BlendMode::Color,
+            15 => BlendMode::Luminosity,
+            _ => return None,
+        };
+        Some(mode)
+    }
+}
+
+pub struct Layer {
+    pub id: u32,
+    pub name: String,
+    pub target: RenderTarget,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    pub visible: bool,
+    pub locked: bool,
+}
+
+impl Layer {
+    pub fn new(id: u32, name: String, target: RenderTarget) -> Self {
+        Self {
+            id,
+            name,
+            target,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            visible: true,
+            locked: false,
+        }
+    }
+
+    /// Whether strokes and other edits may change this layer's pixels.
+    pub fn editable(&self) -> bool {
+        self.visible && !self.locked
+    }
+
+    pub fn info(&self) -> LayerInfo {
+        LayerInfo {
+            id: self.id,
+            name: self.name.clone(),
+            opacity: self.opacity,
+            blend_mode: self.blend_mode as u32,
+            visible: self.visible,
+            locked: self.locked,
+        }
+    }
+}
+
+/// Layer properties as reported to JS.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerInfo {
+    pub id: u32,
+    pub name: String,
+    pub opacity: f32,
+    // BlendMode discriminant, so JS can compare against the exported enum
+    pub blend_mode: u32,
+    pub visible: bool,
+    pub locked: bool,
+}
+
+/// The document's layers, ordered bottom to top.
+pub struct LayerStack {
+    layers: Vec<Layer>,
+    active: usize,
+    next_id: u32,
+}
+
+impl LayerStack {
+    pub fn new() -> Self {
+        Self {
+            layers: Vec::new(),
+            active: 0,
+            next_id: 1,
+        }
+    }
+
+    /// Reserves an id for a layer that is about to be created.
+    pub fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Layer> {
+        self.layers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Layer> {
+        self.layers.iter_mut()
+    }
+
+    pub fn index_of(&self, id: u32) -> Result<usize, JsValue> {
+        self.layers
+            .iter()
+            .position(|layer| layer.id == id)
+            .ok_or_else(|| JsValue::from_str(format!("No layer with id {}", id).as_str()))
+    }
+
+    pub fn get(&self, id: u32) -> Result<&Layer, JsValue> {
+        let index = self.index_of(id)?;
+        Ok(&self.layers[index])
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Result<&mut Layer, JsValue> {
+        let index = self.index_of(id)?;
+        Ok(&mut self.layers[index])
+    }
+
+    pub fn active(&self) -> Option<&Layer> {
+        self.layers.get(self.active)
+    }
+
+    pub fn set_active(&mut self, id: u32) -> Result<(), JsValue> {
+        self.active = self.index_of(id)?;
+        Ok(())
+    }
+
+    /// Inserts `layer` directly above the active layer and makes it active.
+    pub fn insert_above_active(&mut self, layer: Layer) {
+        let index = if self.layers.is_empty() {
+            0
+        } else {
+            self.active + 1
+        };
+        self.layers.insert(index, layer);
+        self.active = index;
+    }
+
+    /// Swaps in a whole new set of layers, returning the old ones so their
+    /// targets can be deleted. New layers should take their ids from `next_id`.
+    pub fn replace(&mut self, layers: Vec<Layer>, active: usize) -> Vec<Layer> {
+        self.active = active.min(layers.len().saturating_sub(1));
+        std::mem::replace(&mut self.layers, layers)
+    }
+
+    /// Removes and returns the layer, keeping the active index on a neighbour.
+    pub fn remove(&mut self, id: u32) -> Result<Layer, JsValue> {
+        let index = self.index_of(id)?;
+        let layer = self.layers.remove(index);
+        if self.active > index || self.active >= self.layers.len() {
+            self.active = self.active.saturating_sub(1);
+        }
+        Ok(layer)
+    }
+
+    /// Moves the layer to `index`, counted from the bottom of the stack.
+    pub fn move_to(&mut self, id: u32, index: usize) -> Result<(), JsValue> {
+        let from = self.index_of(id)?;
+        let active_id = self.layers[self.active].id;
+        let layer = self.layers.remove(from);
+        let to = index.min(self.layers.len());
+        self.layers.insert(to, layer);
+        self.active = self.index_of(active_id)?;
+        Ok(())
+    }
+
+    pub fn infos(&self) -> Vec<LayerInfo> {
+        self.layers.iter().map(Layer::info).collect()
+    }
+}
@@ -1,12 +1,39 @@
 #![allow(dead_code)]
 
 mod brush;
+mod codec;
 mod context;
+mod document;
 mod engine;
+mod eyedropper;
+mod fill;
+mod gradient;
+mod history;
+mod layer;
+mod ora;
 mod pointer_state;
+mod psd;
+mod selection;
 mod shader;
+mod shape;
+mod stabilizer;
+mod stroke;
+mod texture;
+mod tool;
+mod transform;
+mod view;
 use context::{get_context, ContextOptions};
 use engine::Engine;
+use eyedropper::EyedropperOptions;
+use fill::FillOptions;
+use gradient::GradientShape;
+use layer::BlendMode;
+use selection::SelectionMode;
+use shape::ShapeOptions;
+use stabilizer::StabilizerMode;
+use texture::{Anchor, ImagePlacement, Rect, Resampling};
+use tool::Tool;
+use view::Affine;
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -22,8 +49,15 @@ pub struct Painter {
 #[wasm_bindgen]
 #[allow(non_snake_case)]
 impl Painter {
+    /// Creates a painter inside `canvasTarget`. The document is
+    /// `documentWidth` x `documentHeight` pixels, or the size of the canvas
+    /// when they are omitted, and is shown fitted to the canvas.
     #[wasm_bindgen(constructor)]
-    pub fn new(canvasTarget: &HtmlDivElement) -> Result<Painter, JsValue> {
+    pub fn new(
+        canvasTarget: &HtmlDivElement,
+        documentWidth: Option<u32>,
+        documentHeight: Option<u32>,
+    ) -> Result<Painter, JsValue> {
         // create canvas
         let canvas: Option<HtmlCanvasElement>;
         {
@@ -53,7 +87,7 @@ impl Painter {
             },
         )?);
         // initialize private impl
-        let engine = Engine::new(gl, canvas)?;
+        let engine = Engine::new(gl, canvas, documentWidth, documentHeight)?;
 
         Ok(Self { engine })
     }
@@ -61,4 +95,515 @@ impl Painter {
     pub fn changeColor(&mut self, color: &[f32]) -> Result<(), JsValue> {
         self.engine.borrow_mut().change_color(color)
     }
+
+    /// Sets the view zoom, where 1 shows one document pixel per canvas pixel.
+    /// Zooms around the center of the canvas.
+    pub fn setZoom(&mut self, zoom: f32) {
+        self.engine.borrow_mut().set_zoom(zoom);
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.engine.borrow().zoom()
+    }
+
+    /// Scrolls the view by CSS pixels.
+    pub fn panBy(&mut self, dx: f32, dy: f32) {
+        self.engine.borrow_mut().pan_by(dx, dy);
+    }
+
+    /// Rotates the view clockwise around the center of the canvas.
+    pub fn setRotation(&mut self, degrees: f32) {
+        self.engine.borrow_mut().set_rotation(degrees);
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.engine.borrow().rotation()
+    }
+
+    /// Mirrors the view horizontally. Only the display is flipped, not the document.
+    pub fn setFlipHorizontal(&mut self, flip: bool) {
+        self.engine.borrow_mut().set_flip_horizontal(flip);
+    }
+
+    pub fn flipHorizontal(&self) -> bool {
+        self.engine.borrow().flip_horizontal()
+    }
+
+    /// Restores zoom 1, no pan, rotation or flip.
+    pub fn resetView(&mut self) {
+        self.engine.borrow_mut().reset_view();
+    }
+
+    /// Zooms so the whole document is visible and keeps it fitted as the
+    /// canvas resizes, until the view is zoomed, panned or rotated.
+    pub fn fitToView(&mut self) {
+        self.engine.borrow_mut().fit_view();
+    }
+
+    /// Discards the painting and its history and starts a blank white
+    /// document of the given size.
+    pub fn newDocument(&mut self, width: u32, height: u32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().new_document(width, height)
+    }
+
+    /// Changes the document size without scaling the content, which stays at
+    /// `anchor`. New areas are transparent. Clears undo history.
+    pub fn resizeDocument(
+        &mut self,
+        width: u32,
+        height: u32,
+        anchor: Anchor,
+    ) -> Result<(), JsValue> {
+        self.engine
+            .borrow_mut()
+            .resize_document(width, height, anchor)
+    }
+
+    /// Crops the document to the given rect in document pixels. Clears undo history.
+    pub fn crop(&mut self, x: i32, y: i32, width: i32, height: i32) -> Result<(), JsValue> {
+        self.engine
+            .borrow_mut()
+            .crop(&Rect::new(x, y, width, height))
+    }
+
+    /// Scales the whole document, every layer included, to a new size.
+    /// Clears undo history.
+    pub fn scaleDocument(
+        &mut self,
+        width: u32,
+        height: u32,
+        resampling: Resampling,
+    ) -> Result<(), JsValue> {
+        self.engine
+            .borrow_mut()
+            .scale_document(width, height, resampling)
+    }
+
+    pub fn documentWidth(&self) -> u32 {
+        self.engine.borrow().document_size().0 as u32
+    }
+
+    pub fn documentHeight(&self) -> u32 {
+        self.engine.borrow().document_size().1 as u32
+    }
+
+    /// Encodes the composited document as PNG. Without `transparent` the image
+    /// is flattened onto white.
+    pub fn exportPng(&self, transparent: bool) -> Result<Vec<u8>, JsValue> {
+        self.engine.borrow().export_png(transparent)
+    }
+
+    /// Decodes PNG or JPEG bytes into a new layer, or into the active layer
+    /// replacing its contents. Returns the id of the layer that received the image.
+    pub fn importImage(
+        &mut self,
+        bytes: &[u8],
+        asLayer: bool,
+        placement: ImagePlacement,
+    ) -> Result<u32, JsValue> {
+        self.engine
+            .borrow_mut()
+            .import_image(bytes, asLayer, placement)
+    }
+
+    /// Serializes the whole painting (layers, brush settings and palette) in
+    /// the versioned native format.
+    pub fn saveDocument(&self) -> Result<Vec<u8>, JsValue> {
+        self.engine.borrow().save_document()
+    }
+
+    /// Replaces the current painting with one produced by `saveDocument`.
+    /// Clears undo history.
+    pub fn loadDocument(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        self.engine.borrow_mut().load_document(bytes)
+    }
+
+    /// Encodes the layers as an OpenRaster (.ora) file for use in Krita, MyPaint or GIMP.
+    pub fn exportOra(&self) -> Result<Vec<u8>, JsValue> {
+        self.engine.borrow().export_ora()
+    }
+
+    /// Encodes the layers and a flattened composite as a Photoshop (.psd) file.
+    pub fn exportPsd(&self) -> Result<Vec<u8>, JsValue> {
+        self.engine.borrow().export_psd()
+    }
+
+    /// Replaces the current painting with the layers of an OpenRaster file.
+    /// Nested stacks are flattened. Clears undo history.
+    pub fn importOra(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        self.engine.borrow_mut().import_ora(bytes)
+    }
+
+    /// Sets the swatch palette saved with the document, as flat RGBA values in 0..1.
+    pub fn setPalette(&mut self, colors: &[f32]) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_palette(colors)
+    }
+
+    pub fn palette(&self) -> Vec<f32> {
+        self.engine.borrow().palette()
+    }
+
+    /// Reverts the most recent stroke.
+    pub fn undo(&mut self) -> Result<(), JsValue> {
+        self.engine.borrow_mut().undo()
+    }
+
+    /// Reapplies the most recently undone stroke.
+    pub fn redo(&mut self) -> Result<(), JsValue> {
+        self.engine.borrow_mut().redo()
+    }
+
+    pub fn canUndo(&self) -> bool {
+        self.engine.borrow().can_undo()
+    }
+
+    pub fn canRedo(&self) -> bool {
+        self.engine.borrow().can_redo()
+    }
+
+    /// Limits the memory used by undo history. The oldest entries are dropped first.
+    pub fn setHistoryBudget(&mut self, bytes: usize) {
+        self.engine.borrow_mut().set_history_budget(bytes);
+    }
+
+    /// Adds an empty layer above the active one, makes it active and returns its id.
+    pub fn addLayer(&mut self, name: Option<String>) -> Result<u32, JsValue> {
+        self.engine.borrow_mut().add_layer(name)
+    }
+
+    pub fn removeLayer(&mut self, id: u32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().remove_layer(id)
+    }
+
+    /// Moves a layer to `index` in the stack, where 0 is the bottom.
+    pub fn moveLayer(&mut self, id: u32, index: usize) -> Result<(), JsValue> {
+        self.engine.borrow_mut().move_layer(id, index)
+    }
+
+    pub fn renameLayer(&mut self, id: u32, name: String) -> Result<(), JsValue> {
+        self.engine.borrow_mut().rename_layer(id, name)
+    }
+
+    pub fn setLayerVisible(&mut self, id: u32, visible: bool) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_layer_visible(id, visible)
+    }
+
+    pub fn setLayerLocked(&mut self, id: u32, locked: bool) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_layer_locked(id, locked)
+    }
+
+    pub fn setLayerOpacity(&mut self, id: u32, opacity: f32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_layer_opacity(id, opacity)
+    }
+
+    pub fn setLayerBlendMode(&mut self, id: u32, mode: BlendMode) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_layer_blend_mode(id, mode)
+    }
+
+    /// Selects the layer that strokes are drawn into.
+    pub fn setActiveLayer(&mut self, id: u32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_active_layer(id)
+    }
+
+    pub fn activeLayer(&self) -> u32 {
+        self.engine.borrow().active_layer()
+    }
+
+    /// Returns `{ id, name, opacity, visible, locked }` for every layer, bottom to top.
+    pub fn layers(&self) -> Result<JsValue, JsValue> {
+        let infos = self.engine.borrow().layer_infos();
+        Ok(serde_wasm_bindgen::to_value(&infos)?)
+    }
+
+    /// Sets the brush diameter in canvas pixels.
+    pub fn setBrushSize(&mut self, size: f32) {
+        self.engine.borrow_mut().set_brush_size(size);
+    }
+
+    /// Sets the fraction of the brush radius that is fully opaque, in `[0, 1]`.
+    pub fn setBrushHardness(&mut self, hardness: f32) {
+        self.engine.borrow_mut().set_brush_hardness(hardness);
+    }
+
+    /// Sets the opacity of each brush dab, in `[0, 1]`.
+    pub fn setBrushOpacity(&mut self, opacity: f32) {
+        self.engine.borrow_mut().set_brush_opacity(opacity);
+    }
+
+    /// Sets the distance between brush dabs as a percentage of brush size.
+    pub fn setBrushSpacing(&mut self, percent: f32) {
+        self.engine.borrow_mut().set_brush_spacing(percent);
+    }
+
+    /// Selects how raw pointer input is smoothed, with `strength` in `[0, 1]`.
+    pub fn setStabilizer(&mut self, mode: StabilizerMode, strength: f32) {
+        self.engine.borrow_mut().set_stabilizer(mode, strength);
+    }
+
+    /// Sets how much pen pressure scales brush and eraser size and opacity, each in `[0, 1]`.
+    pub fn setPressureDynamics(&mut self, size: f32, opacity: f32) {
+        self.engine
+            .borrow_mut()
+            .set_pressure_dynamics(size, opacity);
+    }
+
+    /// Sets how much pen tilt widens the brush or eraser and lowers its opacity, each in `[0, 1]`.
+    pub fn setTiltDynamics(&mut self, size: f32, opacity: f32) {
+        self.engine.borrow_mut().set_tilt_dynamics(size, opacity);
+    }
+
+    /// Chooses what dragging on the canvas does. A stylus eraser end always erases.
+    pub fn setTool(&mut self, tool: Tool) {
+        self.engine.borrow_mut().set_tool(tool);
+    }
+
+    pub fn tool(&self) -> Tool {
+        self.engine.borrow().tool()
+    }
+
+    /// Configures the bucket tool. `tolerance` is the largest per-channel
+    /// difference from the clicked color that still fills, in `[0, 1]`;
+    /// `sampleMerged` matches against all visible layers instead of the active
+    /// one; `expand` grows the region by that many pixels, up to 100.
+    pub fn setFillOptions(
+        &mut self,
+        tolerance: f32,
+        contiguous: bool,
+        sampleMerged: bool,
+        expand: u32,
+        antialias: bool,
+    ) {
+        self.engine.borrow_mut().set_fill_options(FillOptions {
+            tolerance,
+            contiguous,
+            sample_merged: sampleMerged,
+            expand,
+            antialias,
+        });
+    }
+
+    /// Fills the region around document pixel `x, y` with the brush color, as
+    /// a click with the bucket tool would.
+    pub fn bucketFill(&mut self, x: f32, y: f32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().bucket_fill(x, y)
+    }
+
+    /// Configures the eyedropper. `size` is the width of the square of pixels
+    /// averaged around the pointer, 1 for a single pixel; `sampleMerged` picks
+    /// from all visible layers instead of the active one.
+    pub fn setEyedropperOptions(&mut self, size: u32, sampleMerged: bool) {
+        self.engine
+            .borrow_mut()
+            .set_eyedropper_options(EyedropperOptions {
+                size,
+                sample_merged: sampleMerged,
+            });
+    }
+
+    /// Registers `callback` to receive each color the eyedropper picks, as a
+    /// straight-alpha RGBA array in `[0, 1]`. The picked color is already the
+    /// brush color when it runs. Pass nothing to unregister.
+    pub fn onColorPicked(&mut self, callback: Option<js_sys::Function>) {
+        self.engine.borrow_mut().set_color_picked_callback(callback);
+    }
+
+    /// Picks the color around document pixel `x, y` as an eyedropper click
+    /// would, returning it, or nothing if the pixels there are transparent.
+    pub fn pickColor(&mut self, x: f32, y: f32) -> Result<Option<Vec<f32>>, JsValue> {
+        let color = self.engine.borrow_mut().pick_color(x, y)?;
+        Engine::report_picked_color(&self.engine);
+        Ok(color.map(|color| color.to_vec()))
+    }
+
+    /// Chooses how new selection outlines combine with the current selection.
+    pub fn setSelectionMode(&mut self, mode: SelectionMode) {
+        self.engine.borrow_mut().set_selection_mode(mode);
+    }
+
+    /// Softens the edges of new selection outlines over `radius` pixels.
+    pub fn setSelectionFeather(&mut self, radius: f32) {
+        self.engine.borrow_mut().set_selection_feather(radius);
+    }
+
+    /// Configures the magic wand. `tolerance` is the largest per-channel
+    /// difference from the clicked color that is still selected, in `[0, 1]`;
+    /// `sampleMerged` matches against all visible layers instead of the active one.
+    pub fn setMagicWandOptions(
+        &mut self,
+        tolerance: f32,
+        contiguous: bool,
+        sampleMerged: bool,
+        antialias: bool,
+    ) {
+        self.engine
+            .borrow_mut()
+            .set_magic_wand_options(FillOptions {
+                tolerance,
+                contiguous,
+                sample_merged: sampleMerged,
+                expand: 0,
+                antialias,
+            });
+    }
+
+    /// Selects around document pixel `x, y` as a magic wand click would.
+    pub fn magicWand(&mut self, x: f32, y: f32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().magic_wand(x, y)
+    }
+
+    /// Whether painting is limited to a selection.
+    pub fn hasSelection(&self) -> bool {
+        self.engine.borrow().has_selection()
+    }
+
+    pub fn selectAll(&mut self) -> Result<(), JsValue> {
+        self.engine.borrow_mut().select_all()
+    }
+
+    /// Drops the selection, so painting reaches the whole layer again.
+    pub fn selectNone(&mut self) -> Result<(), JsValue> {
+        self.engine.borrow_mut().select_none()
+    }
+
+    pub fn invertSelection(&mut self) -> Result<(), JsValue> {
+        self.engine.borrow_mut().invert_selection()
+    }
+
+    /// Softens the edge of the current selection over `radius` pixels.
+    pub fn featherSelection(&mut self, radius: f32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().feather_selection(radius)
+    }
+
+    /// Chooses whether rectangles, ellipses and polygons are outlined with the
+    /// brush, filled with its color, or both. Lines are always drawn.
+    pub fn setShapeOptions(&mut self, outline: bool, fill: bool) {
+        self.engine
+            .borrow_mut()
+            .set_shape_options(ShapeOptions { outline, fill });
+    }
+
+    /// Commits the shape in progress, closing an open polygon.
+    pub fn finishShape(&mut self) -> Result<(), JsValue> {
+        self.engine.borrow_mut().finish_shape()
+    }
+
+    /// Discards the shape in progress.
+    pub fn cancelShape(&mut self) {
+        self.engine.borrow_mut().cancel_shape();
+    }
+
+    /// Sets the gradient tool's stops as flat groups of position and
+    /// straight-alpha RGBA, all in `[0, 1]`, e.g. `[0, 1, 0, 0, 1, 1, 0, 0, 1, 0]`
+    /// for red fading out. Takes up to 16 stops.
+    pub fn setGradientStops(&mut self, stops: &[f32]) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_gradient_stops(stops)
+    }
+
+    /// Chooses the gradient's shape, and whether it is dithered to hide banding.
+    pub fn setGradientOptions(&mut self, shape: GradientShape, dither: bool) {
+        self.engine.borrow_mut().set_gradient_options(shape, dither);
+    }
+
+    /// Fills the active layer, or the selection, with the gradient from
+    /// document pixel `x0, y0` to `x1, y1`, as a drag with the gradient tool would.
+    pub fn drawGradient(&mut self, x0: f32, y0: f32, x1: f32, y1: f32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().draw_gradient((x0, y0), (x1, y1))
+    }
+
+    /// The selected pixels of the active layer, or the whole layer without a
+    /// selection, as PNG bytes for the clipboard.
+    pub fn copy(&self) -> Result<Vec<u8>, JsValue> {
+        self.engine.borrow().copy()
+    }
+
+    /// Copies the selected pixels as PNG bytes, then clears them from the layer.
+    pub fn cut(&mut self) -> Result<Vec<u8>, JsValue> {
+        self.engine.borrow_mut().cut()
+    }
+
+    /// Pastes PNG or JPEG bytes as floating pixels on a new layer, centered in
+    /// the view. Move them with the transform tool or methods, then
+    /// `commitTransform`. Returns the new layer's id.
+    pub fn paste(&mut self, bytes: &[u8]) -> Result<u32, JsValue> {
+        self.engine.borrow_mut().paste(bytes)
+    }
+
+    /// Lifts the selected pixels of the active layer, or the whole layer
+    /// without a selection, into a floating copy for the transform methods
+    /// below. The transform tool does this on its first drag.
+    pub fn beginTransform(&mut self) -> Result<(), JsValue> {
+        self.engine.borrow_mut().begin_transform()
+    }
+
+    /// Moves the floating pixels by `dx, dy` document pixels.
+    pub fn transformTranslate(&mut self, dx: f32, dy: f32) -> Result<(), JsValue> {
+        self.engine
+            .borrow_mut()
+            .transform_by(&Affine::translate(dx, dy))
+    }
+
+    /// Scales the floating pixels around their center.
+    pub fn transformScale(&mut self, sx: f32, sy: f32) -> Result<(), JsValue> {
+        self.engine
+            .borrow_mut()
+            .transform_by(&Affine::scale(sx, sy))
+    }
+
+    /// Rotates the floating pixels clockwise around their center.
+    pub fn transformRotate(&mut self, degrees: f32) -> Result<(), JsValue> {
+        self.engine
+            .borrow_mut()
+            .transform_by(&Affine::rotate(degrees.to_radians()))
+    }
+
+    /// Mirrors the floating pixels horizontally, or vertically, around their center.
+    pub fn transformFlip(&mut self, horizontal: bool) -> Result<(), JsValue> {
+        let (sx, sy) = if horizontal { (-1.0, 1.0) } else { (1.0, -1.0) };
+        self.engine
+            .borrow_mut()
+            .transform_by(&Affine::scale(sx, sy))
+    }
+
+    /// Shears the floating pixels around their center by the given angles.
+    pub fn transformSkew(&mut self, xDegrees: f32, yDegrees: f32) -> Result<(), JsValue> {
+        self.engine
+            .borrow_mut()
+            .transform_by(&Affine::skew(xDegrees.to_radians(), yDegrees.to_radians()))
+    }
+
+    /// Moves one corner of the floating pixels to document point `x, y` for a
+    /// perspective or free distortion. Corners are numbered clockwise from the top left.
+    pub fn setTransformCorner(&mut self, index: usize, x: f32, y: f32) -> Result<(), JsValue> {
+        self.engine.borrow_mut().set_transform_corner(index, x, y)
+    }
+
+    pub fn isTransforming(&self) -> bool {
+        self.engine.borrow().is_transforming()
+    }
+
+    /// Draws the floating pixels into the layer, resampled with `resampling`,
+    /// as one undoable edit. Switching tools commits with bicubic resampling.
+    pub fn commitTransform(&mut self, resampling: Resampling) -> Result<(), JsValue> {
+        self.engine.borrow_mut().commit_transform(resampling)
+    }
+
+    /// Puts the floating pixels back where they were lifted from.
+    pub fn cancelTransform(&mut self) {
+        self.engine.borrow_mut().cancel_transform();
+    }
+
+    /// Sets the eraser diameter in document pixels, from 1 to 1000.
+    pub fn setEraserSize(&mut self, size: f32) {
+        self.engine.borrow_mut().set_eraser_size(size);
+    }
+
+    /// Sets the fraction of the eraser radius that fully erases, in `[0, 1]`.
+    pub fn setEraserHardness(&mut self, hardness: f32) {
+        self.engine.borrow_mut().set_eraser_hardness(hardness);
+    }
+
+    /// Sets how much each eraser dab removes, in `[0, 1]`.
+    pub fn setEraserOpacity(&mut self, opacity: f32) {
+        self.engine.borrow_mut().set_eraser_opacity(opacity);
+    }
 }
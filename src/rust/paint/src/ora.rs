@@ -0,0 +1,343 @@
+use super::codec::{self, Image};
+use super::layer::BlendMode;
+use std::io::{Cursor, Read, Write};
+use wasm_bindgen::prelude::*;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const MIMETYPE: &str = "image/openraster";
+// longest side of Thumbnails/thumbnail.png, as required by the spec
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// One raster layer of an OpenRaster file, sized to the whole document.
+pub struct OraLayer {
+    pub name: String,
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend_mode: BlendMode,
+    pub image: Image,
+}
+
+/// A decoded OpenRaster file. Layers are ordered bottom to top like `LayerStack`.
+pub struct OraDocument {
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<OraLayer>,
+}
+
+fn zip_error(err: zip::result::ZipError) -> JsValue {
+    JsValue::from_str(format!("OpenRaster zip error: {}", err).as_str())
+}
+
+fn io_error(err: std::io::Error) -> JsValue {
+    JsValue::from_str(format!("OpenRaster io error: {}", err).as_str())
+}
+
+fn composite_op(mode: BlendMode) -> &'static str {
+    match mode {
+        BlendMode::Normal => "svg:src-over",
+        BlendMode::Multiply => "svg:multiply",
+        BlendMode::Screen => "svg:screen",
+        BlendMode::Overlay => "svg:overlay",
+        BlendMode::Darken => "svg:darken",
+        BlendMode::Lighten => "svg:lighten",
+        BlendMode::ColorDodge => "svg:color-dodge",
+        BlendMode::ColorBurn => "svg:color-burn",
+        BlendMode::HardLight => "svg:hard-light",
+        BlendMode::SoftLight => "svg:soft-light",
+        BlendMode::Difference => "svg:difference",
+        BlendMode::Exclusion => "svg:exclusion",
+        BlendMode::Hue => "svg:hue",
+        BlendMode::Saturation => "svg:saturation",
+        BlendMode::Color => "svg:color",
+        BlendMode::Luminosity => "svg:luminosity",
+    }
+}
+
+fn parse_composite_op(op: &str) -> BlendMode {
+    match op {
+        "svg:multiply" => BlendMode::Multiply,
+        "svg:screen" => BlendMode::Screen,
+        "svg:overlay" => BlendMode::Overlay,
+        "svg:darken" => BlendMode::Darken,
+        "svg:lighten" => BlendMode::Lighten,
+        "svg:color-dodge" => BlendMode::ColorDodge,
+        "svg:color-burn" => BlendMode::ColorBurn,
+        "svg:hard-light" => BlendMode::HardLight,
+        "svg:soft-light" => BlendMode::SoftLight,
+        "svg:difference" => BlendMode::Difference,
+        "svg:exclusion" => BlendMode::Exclusion,
+        "svg:hue" => BlendMode::Hue,
+        "svg:saturation" => BlendMode::Saturation,
+        "svg:color" => BlendMode::Color,
+        "svg:luminosity" => BlendMode::Luminosity,
+        // src-over and anything we can't represent
+        _ => BlendMode::Normal,
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// box-filters straight RGBA8 down so its longest side fits `max_size`
+fn thumbnail(image: &Image, max_size: u32) -> Image {
+    let scale = (max_size as f32 / image.width.max(image.height) as f32).min(1.0);
+    let width = ((image.width as f32 * scale).round() as u32).max(1);
+    let height = ((image.height as f32 * scale).round() as u32).max(1);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        let y0 = y * image.height / height;
+        let y1 = ((y + 1) * image.height / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = x * image.width / width;
+            let x1 = ((x + 1) * image.width / width).max(x0 + 1);
+            // average in premultiplied space so transparent pixels don't bleed color
+            let mut sum = [0u32; 4];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let i = ((sy * image.width + sx) * 4) as usize;
+                    let a = image.pixels[i + 3] as u32;
+                    for (total, c) in sum.iter_mut().zip(&image.pixels[i..i + 3]) {
+                        *total += *c as u32 * a / 255;
+                    }
+                    sum[3] += a;
+                }
+            }
+            let count = (x1 - x0) * (y1 - y0);
+            let a = sum[3] / count;
+            for c in sum.iter().take(3) {
+                let premultiplied = c / count;
+                let straight = (premultiplied * 255).checked_div(a).unwrap_or(0);
+                pixels.push(straight.min(255) as u8);
+            }
+            pixels.push(a as u8);
+        }
+    }
+    Image {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Writes an OpenRaster zip. `merged` is the flattened document in straight alpha.
+pub fn encode(
+    width: u32,
+    height: u32,
+    layers: &[OraLayer],
+    merged: &Image,
+) -> Result<Vec<u8>, JsValue> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // PNGs are already compressed, so they are stored as-is too
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // the mimetype must be the first entry and uncompressed so it can be sniffed
+    zip.start_file("mimetype", stored).map_err(zip_error)?;
+    zip.write_all(MIMETYPE.as_bytes()).map_err(io_error)?;
+
+    let mut stack = format!(
+        "<?xml version='1.0' encoding='UTF-8'?>\n\
+         <image version=\"0.0.3\" w=\"{}\" h=\"{}\">\n<stack>\n",
+        width, height
+    );
+    // stack.xml lists layers top to bottom
+    for (index, layer) in layers.iter().enumerate().rev() {
+        let src = format!("data/layer{}.png", index);
+        stack.push_str(
+            format!(
+                "<layer name=\"{}\" src=\"{}\" x=\"0\" y=\"0\" opacity=\"{:.3}\" \
+                 visibility=\"{}\" composite-op=\"{}\"/>\n",
+                escape_xml(&layer.name),
+                src,
+                layer.opacity,
+                if layer.visible { "visible" } else { "hidden" },
+                composite_op(layer.blend_mode),
+            )
+            .as_str(),
+        );
+        let image = &layer.image;
+        let bytes = codec::encode_png(image.width, image.height, 4, &image.pixels)?;
+        zip.start_file(src, stored).map_err(zip_error)?;
+        zip.write_all(&bytes).map_err(io_error)?;
+    }
+    stack.push_str("</stack>\n</image>\n");
+    zip.start_file("stack.xml", deflated).map_err(zip_error)?;
+    zip.write_all(stack.as_bytes()).map_err(io_error)?;
+
+    let bytes = codec::encode_png(merged.width, merged.height, 4, &merged.pixels)?;
+    zip.start_file("mergedimage.png", stored)
+        .map_err(zip_error)?;
+    zip.write_all(&bytes).map_err(io_error)?;
+
+    let thumb = thumbnail(merged, THUMBNAIL_SIZE);
+    let bytes = codec::encode_png(thumb.width, thumb.height, 4, &thumb.pixels)?;
+    zip.start_file("Thumbnails/thumbnail.png", stored)
+        .map_err(zip_error)?;
+    zip.write_all(&bytes).map_err(io_error)?;
+
+    Ok(zip.finish().map_err(zip_error)?.into_inner())
+}
+
+fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<Vec<u8>, JsValue> {
+    let mut file = archive.by_name(name).map_err(zip_error)?;
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut bytes).map_err(io_error)?;
+    Ok(bytes)
+}
+
+// attributes that nested stacks pass down to their layers
+#[derive(Clone, Copy)]
+struct Inherited {
+    x: i64,
+    y: i64,
+    opacity: f32,
+    visible: bool,
+}
+
+/// Reads an OpenRaster zip. Nested stacks are flattened into a single list of
+/// layers, with the group's offset, opacity and visibility folded in. Documents
+/// wider or taller than `max_size` are rejected before any layer is placed.
+pub fn decode(bytes: &[u8], max_size: u32) -> Result<OraDocument, JsValue> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(zip_error)?;
+    if let Ok(mimetype) = read_entry(&mut archive, "mimetype") {
+        if mimetype.trim_ascii() != MIMETYPE.as_bytes() {
+            return Err("Not an OpenRaster file".into());
+        }
+    }
+    let stack_xml = read_entry(&mut archive, "stack.xml")?;
+    let stack_xml = String::from_utf8(stack_xml)
+        .map_err(|_| JsValue::from_str("stack.xml is not valid UTF-8"))?;
+    let xml = roxmltree::Document::parse(&stack_xml)
+        .map_err(|err| JsValue::from_str(format!("stack.xml parse error: {}", err).as_str()))?;
+
+    let image = xml.root_element();
+    if !image.has_tag_name("image") {
+        return Err("stack.xml has no image element".into());
+    }
+    let dimension = |name: &str| -> Result<u32, JsValue> {
+        image
+            .attribute(name)
+            .and_then(|value| value.parse::<u32>().ok())
+            .filter(|value| *value > 0)
+            .ok_or_else(|| JsValue::from_str(format!("stack.xml has no valid {}", name).as_str()))
+    };
+    let (width, height) = (dimension("w")?, dimension("h")?);
+    if width > max_size || height > max_size {
+        return Err(JsValue::from_str(
+            format!(
+                "OpenRaster size {}x{} is larger than {}x{}",
+                width, height, max_size, max_size
+            )
+            .as_str(),
+        ));
+    }
+    let root = image
+        .children()
+        .find(|node| node.has_tag_name("stack"))
+        .ok_or("stack.xml has no root stack")?;
+
+    let mut layers = Vec::new();
+    let inherited = Inherited {
+        x: 0,
+        y: 0,
+        opacity: 1.0,
+        visible: true,
+    };
+    read_stack(&mut archive, root, inherited, width, height, &mut layers)?;
+    // stack.xml lists layers top to bottom
+    layers.reverse();
+    if layers.is_empty() {
+        return Err("OpenRaster file has no raster layers".into());
+    }
+    Ok(OraDocument {
+        width,
+        height,
+        layers,
+    })
+}
+
+fn read_stack(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    stack: roxmltree::Node,
+    parent: Inherited,
+    width: u32,
+    height: u32,
+    layers: &mut Vec<OraLayer>,
+) -> Result<(), JsValue> {
+    for node in stack.children().filter(|node| node.is_element()) {
+        let number = |name: &str| node.attribute(name).and_then(|v| v.parse::<f32>().ok());
+        let inherited = Inherited {
+            x: parent.x.saturating_add(number("x").unwrap_or(0.0) as i64),
+            y: parent.y.saturating_add(number("y").unwrap_or(0.0) as i64),
+            opacity: parent.opacity * number("opacity").unwrap_or(1.0).clamp(0.0, 1.0),
+            visible: parent.visible && node.attribute("visibility") != Some("hidden"),
+        };
+        if node.has_tag_name("stack") {
+            read_stack(archive, node, inherited, width, height, layers)?;
+            continue;
+        }
+        if !node.has_tag_name("layer") {
+            continue;
+        }
+        let src = node.attribute("src").ok_or("Layer has no src")?;
+        let source = codec::decode_image(&read_entry(archive, src)?)?;
+        layers.push(OraLayer {
+            name: node.attribute("name").unwrap_or("Layer").to_string(),
+            opacity: inherited.opacity,
+            visible: inherited.visible,
+            blend_mode: parse_composite_op(node.attribute("composite-op").unwrap_or("")),
+            image: place(&source, inherited.x, inherited.y, width, height)?,
+        });
+    }
+    Ok(())
+}
+
+// copies `source` into a transparent document-sized image at `x, y`
+fn place(source: &Image, x: i64, y: i64, width: u32, height: u32) -> Result<Image, JsValue> {
+    if x == 0 && y == 0 && source.width == width && source.height == height {
+        return Ok(Image {
+            width,
+            height,
+            pixels: source.pixels.clone(),
+        });
+    }
+    let len = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|len| len.checked_mul(4))
+        .ok_or("OpenRaster image is too large")?;
+    let mut pixels = vec![0u8; len];
+    let (width, height) = (width as i64, height as i64);
+    let (source_width, source_height) = (source.width as i64, source.height as i64);
+    let left = x.max(0);
+    let right = x.saturating_add(source_width).min(width);
+    let top = y.max(0);
+    let bottom = y.saturating_add(source_height).min(height);
+    if left < right {
+        let row_len = ((right - left) * 4) as usize;
+        for dest_y in top..bottom {
+            let row = dest_y - y;
+            let src_start = ((row * source_width + left - x) * 4) as usize;
+            let dest_start = ((dest_y * width + left) * 4) as usize;
+            pixels[dest_start..dest_start + row_len]
+                .copy_from_slice(&source.pixels[src_start..src_start + row_len]);
+        }
+    }
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
@@ -1,17 +1,130 @@
+use web_sys::PointerEvent;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointerType {
+    Mouse,
+    Pen,
+    Touch,
+    Unknown,
+}
+
+impl PointerType {
+    pub fn parse(pointer_type: &str) -> Self {
+        match pointer_type {
+            "mouse" => PointerType::Mouse,
+            "pen" => PointerType::Pen,
+            "touch" => PointerType::Touch,
+            _ => PointerType::Unknown,
+        }
+    }
+}
+
+/// A single pointer sample, with the position given in the engine's drawing space.
+#[derive(Clone, Copy, Debug)]
+pub struct PointerSample {
+    pub x: f32,
+    pub y: f32,
+    pub pressure: f32,
+    pub tilt_x: f32,
+    pub tilt_y: f32,
+    pub twist: f32,
+    pub pointer_type: PointerType,
+    pub pointer_id: i32,
+}
+
+impl PointerSample {
+    pub fn from_event(event: &PointerEvent, x: f32, y: f32) -> Self {
+        let pointer_type = PointerType::parse(&event.pointer_type());
+        // mice report a constant 0.5 while a button is held, which would halve
+        // every pressure-dependent brush setting
+        let pressure = match pointer_type {
+            PointerType::Mouse => 1.0,
+            _ => event.pressure().clamp(0.0, 1.0),
+        };
+        Self {
+            x,
+            y,
+            pressure,
+            tilt_x: event.tilt_x() as f32,
+            tilt_y: event.tilt_y() as f32,
+            twist: event.twist() as f32,
+            pointer_type,
+            pointer_id: event.pointer_id(),
+        }
+    }
+
+    /// Linearly interpolates position and pen attributes towards `other`.
+    pub fn lerp(&self, other: &PointerSample, t: f32) -> PointerSample {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        PointerSample {
+            x: lerp(self.x, other.x),
+            y: lerp(self.y, other.y),
+            pressure: lerp(self.pressure, other.pressure),
+            tilt_x: lerp(self.tilt_x, other.tilt_x),
+            tilt_y: lerp(self.tilt_y, other.tilt_y),
+            twist: lerp(self.twist, other.twist),
+            ..*other
+        }
+    }
+
+    pub fn distance(&self, other: &PointerSample) -> f32 {
+        (other.x - self.x).hypot(other.y - self.y)
+    }
+
+    /// Tilt away from the surface normal, normalized to `[0, 1]`.
+    pub fn tilt(&self) -> f32 {
+        (self.tilt_x.hypot(self.tilt_y) / 90.0).min(1.0)
+    }
+}
+
 pub struct PointerState {
     pressed: bool,
+    pointer_id: Option<i32>,
+    sample: Option<PointerSample>,
 }
 
 impl PointerState {
     pub fn new() -> Self {
-        Self { pressed: false }
+        Self {
+            pressed: false,
+            pointer_id: None,
+            sample: None,
+        }
     }
 
     pub fn set_pressed(&mut self, pressed: bool) {
         self.pressed = pressed;
+        if !pressed {
+            self.pointer_id = None;
+        }
     }
 
     pub fn pressed(&self) -> bool {
         self.pressed
     }
+
+    /// Starts tracking `pointer_id`. Returns false if a different pointer is already down.
+    pub fn capture(&mut self, pointer_id: i32) -> bool {
+        match self.pointer_id {
+            Some(id) if id != pointer_id => false,
+            _ => {
+                self.pointer_id = Some(pointer_id);
+                self.pressed = true;
+                true
+            }
+        }
+    }
+
+    /// Whether events from `pointer_id` belong to the pointer being tracked.
+    pub fn is_captured(&self, pointer_id: i32) -> bool {
+        self.pointer_id == Some(pointer_id)
+    }
+
+    pub fn set_sample(&mut self, sample: PointerSample) {
+        self.sample = Some(sample);
+    }
+
+    pub fn sample(&self) -> Option<&PointerSample> {
+        self.sample.as_ref()
+    }
 }
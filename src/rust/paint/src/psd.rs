@@ -0,0 +1,379 @@
+use super::codec::Image;
+use super::layer::BlendMode;
+use wasm_bindgen::prelude::*;
+
+// Photoshop refuses anything larger in a plain PSD (PSB is needed beyond this)
+const MAX_SIZE: u32 = 30000;
+
+/// One raster layer of a PSD file, sized to the whole document.
+pub struct PsdLayer {
+    pub name: String,
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend_mode: BlendMode,
+    pub image: Image,
+}
+
+fn blend_key(mode: BlendMode) -> &'static [u8; 4] {
+    match mode {
+        BlendMode::Normal => b"norm",
+        BlendMode::Multiply => b"mul ",
+        BlendMode::Screen => b"scrn",
+        BlendMode::Overlay => b"over",
+        BlendMode::Darken => b"dark",
+        BlendMode::Lighten => b"lite",
+        BlendMode::ColorDodge => b"div ",
+        BlendMode::ColorBurn => b"idiv",
+        BlendMode::HardLight => b"hLit",
+        BlendMode::SoftLight => b"sLit",
+        BlendMode::Difference => b"diff",
+        BlendMode::Exclusion => b"smud",
+        BlendMode::Hue => b"hue ",
+        BlendMode::Saturation => b"sat ",
+        BlendMode::Color => b"colr",
+        BlendMode::Luminosity => b"lum ",
+    }
+}
+
+// big-endian writer for the PSD structures
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn i16(&mut self, value: i16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn pad_to(&mut self, start: usize, multiple: usize) {
+        while !(self.bytes.len() - start).is_multiple_of(multiple) {
+            self.bytes.push(0);
+        }
+    }
+
+    // reserves a u32 length field, returning its position for `end_section`
+    fn begin_section(&mut self) -> usize {
+        self.u32(0);
+        self.bytes.len()
+    }
+
+    fn end_section(&mut self, start: usize) {
+        let length = (self.bytes.len() - start) as u32;
+        self.bytes[start - 4..start].copy_from_slice(&length.to_be_bytes());
+    }
+}
+
+// PackBits-compresses one row of samples
+fn pack_bits(row: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < row.len() {
+        // count a run of identical bytes
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+        if run > 1 {
+            out.push((1 - run as i32) as i8 as u8);
+            out.push(row[i]);
+            i += run;
+            continue;
+        }
+        // otherwise copy literals until the next run of 3 or more
+        let start = i;
+        while i < row.len() && i - start < 128 {
+            if i + 2 < row.len() && row[i] == row[i + 1] && row[i] == row[i + 2] {
+                break;
+            }
+            i += 1;
+        }
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&row[start..i]);
+    }
+}
+
+// RLE-compresses one channel of straight RGBA8 pixels, returning the row byte
+// counts and the packed rows
+fn pack_channel(image: &Image, channel: usize) -> (Vec<u16>, Vec<u8>) {
+    let width = image.width as usize;
+    let mut counts = Vec::with_capacity(image.height as usize);
+    let mut packed = Vec::new();
+    let mut row = Vec::with_capacity(width);
+    for pixels in image.pixels.chunks_exact(width * 4) {
+        row.clear();
+        row.extend(pixels.chunks_exact(4).map(|px| px[channel]));
+        let start = packed.len();
+        pack_bits(&row, &mut packed);
+        counts.push((packed.len() - start) as u16);
+    }
+    (counts, packed)
+}
+
+// Pascal string in Mac Roman, approximated here by replacing non-ASCII with '?'
+fn pascal_name(name: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = name
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .take(255)
+        .collect();
+    bytes.insert(0, bytes.len() as u8);
+    bytes
+}
+
+/// Writes an 8-bit RGB PSD with one raster layer per entry in `layers`
+/// (bottom to top) and `merged`, the flattened document, as the image data.
+/// All pixels are straight-alpha RGBA8, top row first.
+pub fn encode(
+    width: u32,
+    height: u32,
+    layers: &[PsdLayer],
+    merged: &Image,
+) -> Result<Vec<u8>, JsValue> {
+    if width > MAX_SIZE || height > MAX_SIZE {
+        return Err(JsValue::from_str(
+            format!("PSD files are limited to {0}x{0} pixels", MAX_SIZE).as_str(),
+        ));
+    }
+    let mut w = Writer { bytes: Vec::new() };
+
+    // file header
+    w.raw(b"8BPS");
+    w.u16(1);
+    w.raw(&[0; 6]);
+    w.u16(4); // channels: RGB plus alpha
+    w.u32(height);
+    w.u32(width);
+    w.u16(8); // bits per channel
+    w.u16(3); // RGB color mode
+
+    // color mode data and image resources are empty
+    w.u32(0);
+    w.u32(0);
+
+    // layer and mask information
+    let layer_and_mask = w.begin_section();
+    let layer_info = w.begin_section();
+    // negative: the merged image's alpha channel holds the document transparency
+    w.i16(-(layers.len() as i16));
+
+    // channel ids in the order the data is written; -1 is transparency
+    let channels: [(i16, usize); 4] = [(-1, 3), (0, 0), (1, 1), (2, 2)];
+    let packed: Vec<Vec<(Vec<u16>, Vec<u8>)>> = layers
+        .iter()
+        .map(|layer| {
+            channels
+                .iter()
+                .map(|(_, index)| pack_channel(&layer.image, *index))
+                .collect()
+        })
+        .collect();
+
+    for (layer, packed) in layers.iter().zip(packed.iter()) {
+        // bounds: top, left, bottom, right
+        w.u32(0);
+        w.u32(0);
+        w.u32(height);
+        w.u32(width);
+        w.u16(channels.len() as u16);
+        for ((id, _), (counts, data)) in channels.iter().zip(packed.iter()) {
+            w.i16(*id);
+            // compression flag, row counts and packed rows
+            w.u32((2 + counts.len() * 2 + data.len()) as u32);
+        }
+        w.raw(b"8BIM");
+        w.raw(blend_key(layer.blend_mode));
+        w.u8((layer.opacity.clamp(0.0, 1.0) * 255.0).round() as u8);
+        w.u8(0); // clipping: base
+        w.u8(if layer.visible { 0 } else { 0b10 });
+        w.u8(0); // filler
+
+        let extra = w.begin_section();
+        w.u32(0); // no layer mask
+        w.u32(0); // no blending ranges
+        let name_start = w.bytes.len();
+        w.raw(&pascal_name(&layer.name));
+        w.pad_to(name_start, 4);
+        // the full name as UTF-16, which the Pascal string can't hold
+        let utf16: Vec<u16> = layer.name.encode_utf16().collect();
+        w.raw(b"8BIM");
+        w.raw(b"luni");
+        let luni = w.begin_section();
+        w.u32(utf16.len() as u32);
+        for unit in utf16 {
+            w.u16(unit);
+        }
+        w.pad_to(luni, 4);
+        w.end_section(luni);
+        w.end_section(extra);
+    }
+
+    for packed in packed.iter() {
+        for (counts, data) in packed {
+            w.u16(1); // RLE
+            for count in counts {
+                w.u16(*count);
+            }
+            w.raw(data);
+        }
+    }
+    w.pad_to(layer_info, 2);
+    w.end_section(layer_info);
+    w.u32(0); // no global layer mask
+    w.end_section(layer_and_mask);
+
+    // merged image data: RLE, every row count for every channel first, then the rows
+    let merged_channels: Vec<(Vec<u16>, Vec<u8>)> = (0..4)
+        .map(|channel| pack_channel(merged, channel))
+        .collect();
+    w.u16(1);
+    for (counts, _) in merged_channels.iter() {
+        for count in counts {
+            w.u16(*count);
+        }
+    }
+    for (_, data) in merged_channels.iter() {
+        w.raw(data);
+    }
+    Ok(w.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PackBits decoder, as Photoshop reads the rows back
+    fn unpack_bits(mut packed: &[u8]) -> Vec<u8> {
+        let mut row = Vec::new();
+        while let Some((&header, rest)) = packed.split_first() {
+            let header = header as i8;
+            if header >= 0 {
+                let count = header as usize + 1;
+                row.extend_from_slice(&rest[..count]);
+                packed = &rest[count..];
+            } else if header != -128 {
+                let count = (1 - header as i32) as usize;
+                row.resize(row.len() + count, rest[0]);
+                packed = &rest[1..];
+            } else {
+                packed = rest;
+            }
+        }
+        row
+    }
+
+    fn pack(row: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        pack_bits(row, &mut out);
+        out
+    }
+
+    #[test]
+    fn packs_runs_and_literals() {
+        assert_eq!(pack(&[]), Vec::<u8>::new());
+        assert_eq!(pack(&[7]), vec![0, 7]);
+        assert_eq!(pack(&[7, 7, 7]), vec![254, 7]);
+        assert_eq!(pack(&[1, 2, 3]), vec![2, 1, 2, 3]);
+        // the example from Apple's technical note on PackBits
+        let row = [
+            0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0xaa, 0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0x22,
+            0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+        ];
+        assert_eq!(
+            pack(&row),
+            vec![
+                0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0xfd, 0xaa, 0x03, 0x80, 0x00, 0x2a, 0x22, 0xf7,
+                0xaa
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_long_runs_and_literals() {
+        let run = vec![9; 300];
+        let packed = pack(&run);
+        assert!(packed.chunks(2).all(|pair| pair[0] as i8 >= -127));
+        assert_eq!(unpack_bits(&packed), run);
+
+        let literals: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let packed = pack(&literals);
+        assert_eq!(packed[0], 127);
+        assert_eq!(unpack_bits(&packed), literals);
+    }
+
+    #[test]
+    fn round_trips_mixed_rows() {
+        let mut seed = 1u32;
+        for len in 0..400 {
+            let row: Vec<u8> = (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    // few distinct values, so runs of every length show up
+                    (seed >> 28) as u8 % 3
+                })
+                .collect();
+            assert_eq!(unpack_bits(&pack(&row)), row, "row of {}", len);
+        }
+    }
+
+    #[test]
+    fn packs_channels_row_by_row() {
+        let image = Image {
+            width: 3,
+            height: 2,
+            pixels: vec![
+                1, 2, 3, 255, 1, 2, 3, 255, 1, 2, 3, 255, //
+                4, 5, 6, 0, 7, 8, 9, 0, 4, 5, 6, 0,
+            ],
+        };
+        let (counts, packed) = pack_channel(&image, 0);
+        assert_eq!(counts, vec![2, 4]);
+        assert_eq!(packed, vec![254, 1, 2, 4, 7, 4]);
+        let (counts, packed) = pack_channel(&image, 3);
+        assert_eq!(counts, vec![2, 2]);
+        assert_eq!(packed, vec![254, 255, 254, 0]);
+    }
+
+    #[test]
+    fn writes_rle_layer_and_merged_data() {
+        let image = Image {
+            width: 2,
+            height: 2,
+            pixels: vec![10; 16],
+        };
+        let layer = PsdLayer {
+            name: String::from("Layer"),
+            opacity: 1.0,
+            visible: true,
+            blend_mode: BlendMode::Normal,
+            image: Image {
+                width: 2,
+                height: 2,
+                pixels: vec![10; 16],
+            },
+        };
+        let bytes = encode(2, 2, &[layer], &image).unwrap_or_else(|_| panic!("encode failed"));
+        assert_eq!(&bytes[..4], b"8BPS");
+        // channels, height, width, depth and color mode
+        assert_eq!(&bytes[12..26], &[0, 4, 0, 0, 0, 2, 0, 0, 0, 2, 0, 8, 0, 3]);
+        // merged data: the RLE flag, two row counts per channel, then two bytes per row
+        let merged = &bytes[bytes.len() - (2 + 4 * 2 * 2 + 4 * 2 * 2)..];
+        assert_eq!(&merged[..2], &[0, 1]);
+        assert!(merged[2..18].chunks(2).all(|count| count == [0, 2]));
+        assert!(merged[18..].chunks(2).all(|row| row == [255, 10]));
+    }
+}
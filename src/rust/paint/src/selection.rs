@@ -0,0 +1,217 @@
+use super::fill::Mask;
+use super::shape::Shape;
+use super::texture::Rect;
+use wasm_bindgen::prelude::*;
+
+// sub-scanlines per pixel row when rasterizing outlines
+const SUBSAMPLES: usize = 4;
+
+/// How a new selection outline combines with the current selection.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionMode {
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+fn combine_pixel(mode: SelectionMode, base: u8, shape: u8) -> u8 {
+    match mode {
+        SelectionMode::Replace => shape,
+        SelectionMode::Add => base.max(shape),
+        SelectionMode::Subtract => base.min(255 - shape),
+        SelectionMode::Intersect => base.min(shape),
+    }
+}
+
+/// Antialiased coverage of the closed `path` over `rect`, one byte per pixel,
+/// top row first. Self-intersecting paths use the even-odd rule.
+pub fn rasterize(path: &[(f32, f32)], rect: &Rect) -> Vec<u8> {
+    let (width, height) = (rect.width.max(0) as usize, rect.height.max(0) as usize);
+    let mut coverage = vec![0u8; width * height];
+    if path.len() < 3 {
+        return coverage;
+    }
+    let mut row = vec![0f32; width];
+    let mut crossings = Vec::new();
+    for (y, out) in coverage.chunks_exact_mut(width.max(1)).enumerate() {
+        row.iter_mut().for_each(|c| *c = 0.0);
+        for sub in 0..SUBSAMPLES {
+            let sy = (rect.y + y as i32) as f32 + (sub as f32 + 0.5) / SUBSAMPLES as f32;
+            crossings.clear();
+            for (i, a) in path.iter().enumerate() {
+                let b = path[(i + 1) % path.len()];
+                if (a.1 <= sy) != (b.1 <= sy) {
+                    crossings.push(a.0 + (sy - a.1) / (b.1 - a.1) * (b.0 - a.0));
+                }
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for span in crossings.chunks_exact(2) {
+                let end = (span[1] - rect.x as f32).clamp(0.0, width as f32);
+                // spread the span over the pixels it crosses, partially at its ends
+                let mut x = (span[0] - rect.x as f32).clamp(0.0, width as f32);
+                while x < end {
+                    let next = (x.floor() + 1.0).min(end);
+                    row[x as usize] += next - x;
+                    x = next;
+                }
+            }
+        }
+        for (c, value) in out.iter_mut().zip(row.iter()) {
+            *c = (value / SUBSAMPLES as f32 * 255.0).round().min(255.0) as u8;
+        }
+    }
+    coverage
+}
+
+/// The pixels an outline path touches, with a pixel to spare.
+pub fn path_bounds(path: &[(f32, f32)]) -> Rect {
+    let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+    let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+    for (x, y) in path {
+        min_x = min_x.min(*x);
+        min_y = min_y.min(*y);
+        max_x = max_x.max(*x);
+        max_y = max_y.max(*y);
+    }
+    if min_x > max_x {
+        return Rect::new(0, 0, 0, 0);
+    }
+    Rect::from_bounds(min_x - 1.0, min_y - 1.0, max_x + 1.0, max_y + 1.0)
+}
+
+/// Which pixels painting may touch, and how much. An inactive selection means
+/// nothing is selected, and everything may be painted.
+#[derive(Clone)]
+pub struct Selection {
+    pub mask: Mask,
+    pub active: bool,
+}
+
+impl Selection {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            mask: Mask::new(width, height),
+            active: false,
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.mask.width as i32, self.mask.height as i32)
+    }
+
+    pub fn select_all(&mut self) {
+        self.mask.coverage.iter_mut().for_each(|c| *c = 255);
+        self.active = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.mask.coverage.iter_mut().for_each(|c| *c = 0);
+        self.active = false;
+    }
+
+    /// Swaps selected and unselected pixels. Without a selection there is nothing to invert.
+    pub fn invert(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.mask.coverage.iter_mut().for_each(|c| *c = 255 - *c);
+        self.settle();
+    }
+
+    pub fn feather(&mut self, radius: f32) {
+        if !self.active {
+            return;
+        }
+        self.mask.blur(radius);
+        self.settle();
+    }
+
+    /// Deselects if no pixel is left selected, so painting isn't blocked everywhere.
+    pub fn settle(&mut self) {
+        if self.active && self.mask.coverage.iter().all(|c| *c == 0) {
+            self.active = false;
+        }
+    }
+
+    /// Sets `region` to `base` combined under `mode` with `shape`, the coverage
+    /// of a new outline over `rect`, top row first.
+    pub fn combine(
+        &mut self,
+        base: &Selection,
+        mode: SelectionMode,
+        shape: &[u8],
+        rect: &Rect,
+        region: &Rect,
+    ) {
+        let region = region.intersect(&self.bounds());
+        let width = self.mask.width;
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                let index = y as usize * width + x as usize;
+                let (sx, sy) = (x - rect.x, y - rect.y);
+                let covered = if sx >= 0 && sy >= 0 && sx < rect.width && sy < rect.height {
+                    shape[(sy * rect.width + sx) as usize]
+                } else {
+                    0
+                };
+                let before = if base.active {
+                    base.mask.coverage[index]
+                } else {
+                    0
+                };
+                self.mask.coverage[index] = combine_pixel(mode, before, covered);
+            }
+        }
+        self.active = true;
+    }
+
+    /// Scales `mask`, over the same image, by the selection's coverage.
+    pub fn restrict(&self, mask: &mut Mask) {
+        if !self.active {
+            return;
+        }
+        for (c, s) in mask.coverage.iter_mut().zip(self.mask.coverage.iter()) {
+            *c = (*c as u32 * *s as u32 / 255) as u8;
+        }
+    }
+
+    /// Scales premultiplied RGBA8 `pixels` holding `rect`, top row first, by
+    /// the selection's coverage, or by what it leaves unselected if `inverse`.
+    pub fn mask_pixels(&self, pixels: &mut [u8], rect: &Rect, inverse: bool) {
+        let row_len = rect.width as usize;
+        for (i, px) in pixels.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (rect.x as usize + i % row_len, rect.y as usize + i / row_len);
+            let mut coverage = self.mask.coverage[y * self.mask.width + x] as u32;
+            if inverse {
+                coverage = 255 - coverage;
+            }
+            for channel in px.iter_mut() {
+                *channel = ((*channel as u32 * coverage + 127) / 255) as u8;
+            }
+        }
+    }
+
+    /// `rect` of the mask as RGBA8 with the coverage in every channel, top row first.
+    pub fn rgba(&self, rect: &Rect) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(rect.byte_len());
+        for y in rect.y..rect.y + rect.height {
+            let start = y as usize * self.mask.width + rect.x as usize;
+            for c in &self.mask.coverage[start..start + rect.width as usize] {
+                pixels.extend_from_slice(&[*c; 4]);
+            }
+        }
+        pixels
+    }
+}
+
+/// A selection outline being dragged out. Lassos are polygons with a vertex per pointer move.
+pub struct SelectionDrag {
+    pub shape: Shape,
+    pub mode: SelectionMode,
+    // the selection before the drag started
+    pub base: Selection,
+    // the area the last preview changed
+    pub bounds: Rect,
+}
@@ -1,3 +1,4 @@
+use wasm_bindgen::prelude::*;
 use web_sys::{WebGl2RenderingContext as WGL2, WebGlProgram, WebGlShader};
 
 pub const BRUSH_VERTEX_SHADER_SRC: &str = r#"#version 300 es
@@ -8,9 +9,22 @@ pub const BRUSH_VERTEX_SHADER_SRC: &str = r#"#version 300 es
     precision mediump float;
 #endif
 
-in vec4 position;
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec2 dab_coords;
+layout (location = 2) in float dab_opacity;
+
+// size of the render target in pixels
+uniform vec2 resolution;
+
+out vec2 out_dab_coords;
+out float out_dab_opacity;
+
 void main() {
-    gl_Position = position;
+    out_dab_coords = dab_coords;
+    out_dab_opacity = dab_opacity;
+    // canvas pixels have their origin at the top left
+    vec2 ndc = 2.0 * position / resolution - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
 }
 "#;
 
@@ -22,10 +36,31 @@ pub const BRUSH_FRAGMENT_SHADER_SRC: &str = r#"#version 300 es
     precision mediump float;
 #endif
 
-uniform vec4 color;
+in vec2 out_dab_coords;
+in float out_dab_opacity;
 out vec4 out_color;
+
+uniform vec4 color;
+// fraction of the radius that is fully opaque
+uniform float hardness;
+// limits painting to the selected pixels, when there is a selection
+uniform bool use_selection;
+uniform sampler2D selection;
+
 void main() {
-    out_color = color;
+    float dist = length(out_dab_coords);
+    if (dist > 1.0) {
+        discard;
+    }
+    // keep at least a pixel of falloff so hard brushes are antialiased
+    float edge = min(hardness, 1.0 - fwidth(dist));
+    float mask = 1.0 - smoothstep(edge, 1.0, dist);
+    float alpha = color.a * out_dab_opacity * mask;
+    if (use_selection) {
+        // the selection has the size and row order of the layer drawn into
+        alpha *= texelFetch(selection, ivec2(gl_FragCoord.xy), 0).a;
+    }
+    out_color = vec4(color.rgb * alpha, alpha);
 }
 "#;
 
@@ -40,14 +75,18 @@ pub const QUAD_VERTEX_SHADER_SRC: &str = r#"#version 300 es
 layout (location = 0) in vec2 position;
 layout (location = 1) in vec2 texcoords;
 
+// maps the quad's NDC to the target's, e.g. the view's zoom, pan and rotation
+uniform mat3 transform;
+
 out vec2 out_texcoords;
 
 void main() {
     out_texcoords = texcoords;
-    gl_Position = vec4(position, 0.0, 1.0);
+    gl_Position = vec4((transform * vec3(position, 1.0)).xy, 0.0, 1.0);
 }
 "#;
 
+// composites one layer over the layers below it using the layer's blend mode
 pub const QUAD_FRAGMENT_SHADER_SRC: &str = r#"#version 300 es
 
 #ifdef GL_FRAGMENT_PRECISION_HIGH
@@ -56,14 +95,396 @@ pub const QUAD_FRAGMENT_SHADER_SRC: &str = r#"#version 300 es
     precision mediump float;
 #endif
 
+in vec2 out_texcoords;
+out vec4 out_color;
+// the layer being composited and the result of compositing everything below it,
+// both with premultiplied alpha
+uniform sampler2D tex;
+uniform sampler2D backdrop;
+uniform float opacity;
+// must match the discriminants of layer::BlendMode
+uniform int blend_mode;
+
+const int NORMAL = 0;
+const int MULTIPLY = 1;
+const int SCREEN = 2;
+const int OVERLAY = 3;
+const int DARKEN = 4;
+const int LIGHTEN = 5;
+const int COLOR_DODGE = 6;
+const int COLOR_BURN = 7;
+const int HARD_LIGHT = 8;
+const int SOFT_LIGHT = 9;
+const int DIFFERENCE = 10;
+const int EXCLUSION = 11;
+const int HUE = 12;
+const int SATURATION = 13;
+const int COLOR = 14;
+const int LUMINOSITY = 15;
+
+vec3 screen(vec3 cb, vec3 cs) {
+    return cb + cs - cb * cs;
+}
+
+vec3 hard_light(vec3 cb, vec3 cs) {
+    return mix(cb * 2.0 * cs, screen(cb, 2.0 * cs - 1.0), step(0.5, cs));
+}
+
+float color_dodge(float cb, float cs) {
+    if (cb == 0.0) {
+        return 0.0;
+    }
+    return cs >= 1.0 ? 1.0 : min(1.0, cb / (1.0 - cs));
+}
+
+float color_burn(float cb, float cs) {
+    if (cb == 1.0) {
+        return 1.0;
+    }
+    return cs <= 0.0 ? 0.0 : 1.0 - min(1.0, (1.0 - cb) / cs);
+}
+
+float soft_light(float cb, float cs) {
+    if (cs <= 0.5) {
+        return cb - (1.0 - 2.0 * cs) * cb * (1.0 - cb);
+    }
+    float d = cb <= 0.25 ? ((16.0 * cb - 12.0) * cb + 4.0) * cb : sqrt(cb);
+    return cb + (2.0 * cs - 1.0) * (d - cb);
+}
+
+float lum(vec3 c) {
+    return dot(c, vec3(0.3, 0.59, 0.11));
+}
+
+vec3 clip_color(vec3 c) {
+    float l = lum(c);
+    float n = min(c.r, min(c.g, c.b));
+    float x = max(c.r, max(c.g, c.b));
+    if (n < 0.0) {
+        c = l + (c - l) * l / (l - n);
+    }
+    if (x > 1.0) {
+        c = l + (c - l) * (1.0 - l) / (x - l);
+    }
+    return c;
+}
+
+vec3 set_lum(vec3 c, float l) {
+    return clip_color(c + (l - lum(c)));
+}
+
+float sat(vec3 c) {
+    return max(c.r, max(c.g, c.b)) - min(c.r, min(c.g, c.b));
+}
+
+vec3 set_sat(vec3 c, float s) {
+    float n = min(c.r, min(c.g, c.b));
+    float x = max(c.r, max(c.g, c.b));
+    return x > n ? (c - n) * s / (x - n) : vec3(0.0);
+}
+
+vec3 blend(vec3 cb, vec3 cs) {
+    switch (blend_mode) {
+        case MULTIPLY: return cb * cs;
+        case SCREEN: return screen(cb, cs);
+        case OVERLAY: return hard_light(cs, cb);
+        case DARKEN: return min(cb, cs);
+        case LIGHTEN: return max(cb, cs);
+        case COLOR_DODGE:
+            return vec3(color_dodge(cb.r, cs.r), color_dodge(cb.g, cs.g), color_dodge(cb.b, cs.b));
+        case COLOR_BURN:
+            return vec3(color_burn(cb.r, cs.r), color_burn(cb.g, cs.g), color_burn(cb.b, cs.b));
+        case HARD_LIGHT: return hard_light(cb, cs);
+        case SOFT_LIGHT:
+            return vec3(soft_light(cb.r, cs.r), soft_light(cb.g, cs.g), soft_light(cb.b, cs.b));
+        case DIFFERENCE: return abs(cb - cs);
+        case EXCLUSION: return cb + cs - 2.0 * cb * cs;
+        case HUE: return set_lum(set_sat(cs, sat(cb)), lum(cb));
+        case SATURATION: return set_lum(set_sat(cb, sat(cs)), lum(cb));
+        case COLOR: return set_lum(cs, lum(cb));
+        case LUMINOSITY: return set_lum(cb, lum(cs));
+        default: return cs;
+    }
+}
+
+void main() {
+    vec4 src = texture(tex, out_texcoords) * opacity;
+    vec4 dst = texture(backdrop, out_texcoords);
+    vec3 cs = src.a > 0.0 ? src.rgb / src.a : vec3(0.0);
+    vec3 cb = dst.a > 0.0 ? dst.rgb / dst.a : vec3(0.0);
+    // blend only where there is a backdrop, then source-over
+    vec3 mixed = (1.0 - dst.a) * cs + dst.a * clamp(blend(cb, cs), 0.0, 1.0);
+    out_color = vec4(
+        src.a * mixed + (1.0 - src.a) * dst.rgb,
+        src.a + dst.a * (1.0 - src.a)
+    );
+}
+"#;
+
+// draws the composited document to the screen
+pub const CANVAS_FRAGMENT_SHADER_SRC: &str = r#"#version 300 es
+
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+    precision highp float;
+#else
+    precision mediump float;
+#endif
+
+in vec2 out_texcoords;
+out vec4 out_color;
+uniform sampler2D tex;
+// show transparent areas over a gray checkerboard, fixed to the screen
+uniform bool checkerboard;
+// outline the selection with marching ants, shifted along by `ants_phase` pixels
+uniform bool show_selection;
+uniform sampler2D selection;
+uniform float ants_phase;
+
+void main() {
+    vec4 color = texture(tex, out_texcoords);
+    if (checkerboard) {
+        ivec2 cell = ivec2(gl_FragCoord.xy) / 8;
+        vec3 backdrop = (cell.x + cell.y) % 2 == 0 ? vec3(1.0) : vec3(0.8);
+        color = vec4(color.rgb + backdrop * (1.0 - color.a), 1.0);
+    }
+    // the edge is where being inside changes between neighbouring screen pixels
+    float inside = step(0.5, texture(selection, out_texcoords).a);
+    if (show_selection && fwidth(inside) > 0.0) {
+        float stripe = mod(floor((gl_FragCoord.x + gl_FragCoord.y + ants_phase) / 4.0), 2.0);
+        color = vec4(vec3(stripe), 1.0);
+    }
+    out_color = color;
+}
+"#;
+
+// resamples a premultiplied texture to the size of the bound target. The kernel
+// is stretched by `scale` when shrinking so every source texel contributes
+pub const RESAMPLE_FRAGMENT_SHADER_SRC: &str = r#"#version 300 es
+
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+    precision highp float;
+#else
+    precision mediump float;
+#endif
+
 in vec2 out_texcoords;
 out vec4 out_color;
 uniform sampler2D tex;
+// 0 bilinear (tent), 1 bicubic (Catmull-Rom)
+uniform int kernel;
+// source texels per destination pixel, from 1 to 8 on each axis; larger
+// reductions are split into several passes
+uniform vec2 scale;
+
+float tent(float x) {
+    return max(0.0, 1.0 - abs(x));
+}
+
+float catmull_rom(float x) {
+    x = abs(x);
+    if (x < 1.0) {
+        return (1.5 * x - 2.5) * x * x + 1.0;
+    }
+    if (x < 2.0) {
+        return ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0;
+    }
+    return 0.0;
+}
+
+float weight(float x) {
+    return kernel == 1 ? catmull_rom(x) : tent(x);
+}
 
 void main() {
-    out_color = vec4(texture(tex, out_texcoords).rgb, 1.0);
-    // out_color = vec4(0.0, 1.0, 0.0, 1.0);
-} 
+    ivec2 size = textureSize(tex, 0);
+    // position in texel index space, where texel centers are whole numbers
+    vec2 center = out_texcoords * vec2(size) - 0.5;
+    // capped to bound the loops, however the uniform is set
+    vec2 stretch = clamp(scale, 1.0, 8.0);
+    vec2 radius = (kernel == 1 ? 2.0 : 1.0) * stretch;
+    ivec2 lo = ivec2(ceil(center - radius));
+    ivec2 hi = ivec2(floor(center + radius));
+
+    vec4 sum = vec4(0.0);
+    float total = 0.0;
+    for (int y = lo.y; y <= hi.y; y++) {
+        float wy = weight((float(y) - center.y) / stretch.y);
+        for (int x = lo.x; x <= hi.x; x++) {
+            float w = wy * weight((float(x) - center.x) / stretch.x);
+            ivec2 texel = clamp(ivec2(x, y), ivec2(0), size - 1);
+            sum += texelFetch(tex, texel, 0) * w;
+            total += w;
+        }
+    }
+    vec4 color = total > 0.0 ? sum / total : vec4(0.0);
+    // bicubic overshoots; keep the result valid premultiplied alpha
+    color.a = clamp(color.a, 0.0, 1.0);
+    out_color = vec4(clamp(color.rgb, 0.0, color.a), color.a);
+}
+"#;
+
+// draws the floating pixels of a free transform into the bound target. Each
+// target pixel is mapped back through the transform into the source, which is
+// sampled like the resample shader, widening the kernel where it shrinks
+pub const TRANSFORM_FRAGMENT_SHADER_SRC: &str = r#"#version 300 es
+
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+    precision highp float;
+#else
+    precision mediump float;
+#endif
+
+out vec4 out_color;
+// the floating pixels, in GL row order
+uniform sampler2D tex;
+// maps target pixels to source pixels, both with their origin at the top left
+uniform mat3 inverse;
+uniform float target_height;
+// 0 bilinear (tent), 1 bicubic (Catmull-Rom)
+uniform int kernel;
+
+float tent(float x) {
+    return max(0.0, 1.0 - abs(x));
+}
+
+float catmull_rom(float x) {
+    x = abs(x);
+    if (x < 1.0) {
+        return (1.5 * x - 2.5) * x * x + 1.0;
+    }
+    if (x < 2.0) {
+        return ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0;
+    }
+    return 0.0;
+}
+
+float weight(float x) {
+    return kernel == 1 ? catmull_rom(x) : tent(x);
+}
+
+void main() {
+    vec3 mapped = inverse * vec3(gl_FragCoord.x, target_height - gl_FragCoord.y, 1.0);
+    vec2 source = mapped.xy / mapped.z;
+    // source texels per target pixel, capped to bound the loops
+    vec2 scale = clamp(fwidth(source), 1.0, 8.0);
+    ivec2 size = textureSize(tex, 0);
+    // behind the horizon of a perspective transform, or outside the source
+    if (mapped.z <= 0.0 || any(lessThan(source, vec2(0.0))) || any(greaterThan(source, vec2(size)))) {
+        discard;
+    }
+
+    // position in texel index space, where texel centers are whole numbers
+    vec2 center = source - 0.5;
+    vec2 radius = (kernel == 1 ? 2.0 : 1.0) * scale;
+    ivec2 lo = ivec2(ceil(center - radius));
+    ivec2 hi = ivec2(floor(center + radius));
+
+    vec4 sum = vec4(0.0);
+    float total = 0.0;
+    for (int y = lo.y; y <= hi.y; y++) {
+        float wy = weight((float(y) - center.y) / scale.y);
+        for (int x = lo.x; x <= hi.x; x++) {
+            float w = wy * weight((float(x) - center.x) / scale.x);
+            // beyond the source is transparent, which softens its edges
+            if (x >= 0 && y >= 0 && x < size.x && y < size.y) {
+                sum += texelFetch(tex, ivec2(x, size.y - 1 - y), 0) * w;
+            }
+            total += w;
+        }
+    }
+    vec4 color = total > 0.0 ? sum / total : vec4(0.0);
+    // bicubic overshoots; keep the result valid premultiplied alpha
+    color.a = clamp(color.a, 0.0, 1.0);
+    out_color = vec4(clamp(color.rgb, 0.0, color.a), color.a);
+}
+"#;
+
+// fills the bound target with a gradient dragged from `start` to `end`, over
+// what is already there
+pub const GRADIENT_FRAGMENT_SHADER_SRC: &str = r#"#version 300 es
+
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+    precision highp float;
+#else
+    precision mediump float;
+#endif
+
+// matches gradient::MAX_STOPS
+#define MAX_STOPS 16
+#define TAU 6.28318530718
+
+out vec4 out_color;
+// the drag, in target pixels with their origin at the top left
+uniform vec2 start;
+uniform vec2 end;
+uniform float target_height;
+// 0 linear, 1 radial, 2 conic, 3 reflected, 4 diamond
+uniform int shape;
+uniform int stop_count;
+// ordered positions in [0, 1] and premultiplied colors
+uniform float stop_positions[MAX_STOPS];
+uniform vec4 stop_colors[MAX_STOPS];
+uniform bool dither;
+// limits painting to the selected pixels, when there is a selection
+uniform bool use_selection;
+uniform sampler2D selection;
+
+vec4 color_at(float t) {
+    if (t <= stop_positions[0]) {
+        return stop_colors[0];
+    }
+    for (int i = 1; i < MAX_STOPS; i++) {
+        if (i >= stop_count) {
+            break;
+        }
+        if (t <= stop_positions[i]) {
+            float span = stop_positions[i] - stop_positions[i - 1];
+            float f = span > 0.0 ? (t - stop_positions[i - 1]) / span : 1.0;
+            return mix(stop_colors[i - 1], stop_colors[i], f);
+        }
+    }
+    return stop_colors[stop_count - 1];
+}
+
+// interleaved gradient noise in [0, 1), after Jimenez
+float noise(vec2 p) {
+    return fract(52.9829189 * fract(dot(p, vec2(0.06711056, 0.00583715))));
+}
+
+void main() {
+    vec2 p = vec2(gl_FragCoord.x, target_height - gl_FragCoord.y) - start;
+    vec2 d = end - start;
+    float len2 = max(dot(d, d), 1e-6);
+    // along the drag and across it, in drag lengths
+    float u = dot(p, d) / len2;
+    float v = dot(p, vec2(-d.y, d.x)) / len2;
+    float t;
+    if (shape == 1) {
+        t = length(p) / sqrt(len2);
+    } else if (shape == 2) {
+        t = fract((atan(p.y, p.x) - atan(d.y, d.x)) / TAU);
+    } else if (shape == 3) {
+        t = abs(u);
+    } else if (shape == 4) {
+        t = abs(u) + abs(v);
+    } else {
+        t = u;
+    }
+    vec4 color = color_at(clamp(t, 0.0, 1.0));
+    if (dither) {
+        float offset = (noise(gl_FragCoord.xy) - 0.5) / 255.0;
+        // opaque and clear stops stay exact; only partial alpha ramps are dithered
+        if (color.a > 0.0 && color.a < 1.0) {
+            color.a = clamp(color.a + offset, 0.0, 1.0);
+        }
+        color.rgb = clamp(color.rgb + offset, 0.0, color.a);
+    }
+    if (use_selection) {
+        // the selection has the size and row order of the layer drawn into
+        color *= texelFetch(selection, ivec2(gl_FragCoord.xy), 0).a;
+    }
+    out_color = color;
+}
 "#;
 
 pub fn compile_shader(gl: &WGL2, shader_type: u32, source: &str) -> Result<WebGlShader, String> {
@@ -111,3 +532,18 @@ pub fn link_program(
             .unwrap_or_else(|| String::from("Unknown error creating program object")))
     }
 }
+
+/// Compiles and links a program from vertex and fragment shader sources.
+pub fn build_program(gl: &WGL2, vert_src: &str, frag_src: &str) -> Result<WebGlProgram, JsValue> {
+    let vert = compile_shader(gl, WGL2::VERTEX_SHADER, vert_src).map_err(|shader_log| {
+        JsValue::from_str(format!("Unable to compile vertex shader:\n{}", shader_log).as_str())
+    })?;
+    let frag = compile_shader(gl, WGL2::FRAGMENT_SHADER, frag_src).map_err(|shader_log| {
+        JsValue::from_str(format!("Unable to compile fragment shader:\n{}", shader_log).as_str())
+    })?;
+    let linked = link_program(gl, &vert, &frag)
+        .map_err(|_| JsValue::from_str("Unable to link shader program"));
+    gl.delete_shader(Some(&vert));
+    gl.delete_shader(Some(&frag));
+    linked
+}
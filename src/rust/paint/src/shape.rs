@@ -0,0 +1,333 @@
+use super::brush::Brush;
+use super::stroke::{Dab, MIN_SPACING};
+use super::texture::Rect;
+use std::f32::consts::{FRAC_PI_4, PI};
+
+/// Which parts of a closed shape are drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct ShapeOptions {
+    pub outline: bool,
+    pub fill: bool,
+}
+
+impl Default for ShapeOptions {
+    fn default() -> Self {
+        Self {
+            outline: true,
+            fill: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShapeKind {
+    Line,
+    Rectangle,
+    Ellipse,
+    Polygon,
+}
+
+/// A shape being dragged out, in document pixels.
+pub struct Shape {
+    pub kind: ShapeKind,
+    // the drag start, or every placed vertex of a polygon
+    points: Vec<(f32, f32)>,
+    // where the pointer is now; the drag end or the polygon's next vertex
+    cursor: (f32, f32),
+    // square, circle or 45° steps
+    constrain: bool,
+    // a finished polygon closes on its last vertex rather than the cursor
+    finished: bool,
+}
+
+/// Snaps the direction from `from` to `to` to a multiple of 45°, keeping its length.
+pub fn snap_angle(from: (f32, f32), to: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    let angle = (dy.atan2(dx) / FRAC_PI_4).round() * FRAC_PI_4;
+    (from.0 + length * angle.cos(), from.1 + length * angle.sin())
+}
+
+// moves `to` so the box from `from` is square, keeping the drag direction
+fn snap_square(from: (f32, f32), to: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let side = dx.abs().max(dy.abs());
+    (from.0 + side.copysign(dx), from.1 + side.copysign(dy))
+}
+
+impl Shape {
+    pub fn new(kind: ShapeKind, start: (f32, f32)) -> Self {
+        Self {
+            kind,
+            points: vec![start],
+            cursor: start,
+            constrain: false,
+            finished: false,
+        }
+    }
+
+    pub fn set_cursor(&mut self, cursor: (f32, f32), constrain: bool) {
+        self.cursor = cursor;
+        self.constrain = constrain;
+    }
+
+    /// Places the cursor as a polygon vertex.
+    pub fn add_vertex(&mut self) {
+        let vertex = self.constrained_cursor();
+        self.points.push(vertex);
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn first_point(&self) -> (f32, f32) {
+        self.points[0]
+    }
+
+    pub fn last_point(&self) -> (f32, f32) {
+        *self.points.last().unwrap()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.points.len()
+    }
+
+    fn constrained_cursor(&self) -> (f32, f32) {
+        let last = *self.points.last().unwrap();
+        match (self.constrain, self.kind) {
+            (false, _) => self.cursor,
+            (true, ShapeKind::Line) | (true, ShapeKind::Polygon) => snap_angle(last, self.cursor),
+            (true, _) => snap_square(last, self.cursor),
+        }
+    }
+
+    /// The outline as a path of points, and whether it closes back on itself.
+    pub fn path(&self) -> (Vec<(f32, f32)>, bool) {
+        let start = self.points[0];
+        let end = self.constrained_cursor();
+        match self.kind {
+            ShapeKind::Line => (vec![start, end], false),
+            ShapeKind::Rectangle => (vec![start, (end.0, start.1), end, (start.0, end.1)], true),
+            ShapeKind::Ellipse => {
+                let center = ((start.0 + end.0) / 2.0, (start.1 + end.1) / 2.0);
+                let (rx, ry) = ((end.0 - start.0).abs() / 2.0, (end.1 - start.1).abs() / 2.0);
+                // about one segment per 4 pixels of circumference
+                let segments = ((PI * (rx + ry) / 2.0) as usize).clamp(16, 512);
+                let path = (0..segments)
+                    .map(|i| {
+                        let angle = 2.0 * PI * i as f32 / segments as f32;
+                        (center.0 + rx * angle.cos(), center.1 + ry * angle.sin())
+                    })
+                    .collect();
+                (path, true)
+            }
+            ShapeKind::Polygon => {
+                let mut path = self.points.clone();
+                if !self.finished {
+                    path.push(end);
+                }
+                (path, true)
+            }
+        }
+    }
+
+    /// Dabs of `brush` spaced along the outline.
+    pub fn outline_dabs(&self, brush: &Brush) -> Vec<Dab> {
+        let (mut path, closed) = self.path();
+        if closed {
+            path.push(path[0]);
+        }
+        let step = (brush.spacing * brush.size).max(MIN_SPACING);
+        let dab = |(x, y): (f32, f32)| Dab {
+            x,
+            y,
+            size: brush.size,
+            opacity: brush.opacity,
+        };
+        let mut dabs = vec![dab(path[0])];
+        // distance still to travel before the next dab, carried across corners
+        let mut remaining = step;
+        for segment in path.windows(2) {
+            let (from, to) = (segment[0], segment[1]);
+            let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+            let length = (dx * dx + dy * dy).sqrt();
+            let mut travelled = 0.0;
+            while length - travelled >= remaining {
+                travelled += remaining;
+                let t = travelled / length;
+                dabs.push(dab((from.0 + dx * t, from.1 + dy * t)));
+                remaining = step;
+            }
+            remaining -= length - travelled;
+        }
+        dabs
+    }
+
+    /// Triangles covering the inside of the shape, three points each. Lines have none.
+    pub fn fill_triangles(&self) -> Vec<(f32, f32)> {
+        let (path, closed) = self.path();
+        if !closed || path.len() < 3 {
+            return Vec::new();
+        }
+        triangulate(&path)
+            .iter()
+            .flat_map(|triangle| triangle.iter().map(|i| path[*i]))
+            .collect()
+    }
+
+    /// Document pixels the shape may touch when drawn with `brush`.
+    pub fn bounds(&self, brush: &Brush) -> Rect {
+        let (path, _) = self.path();
+        let pad = brush.size / 2.0 + 1.0;
+        let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+        let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+        for (x, y) in path {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        Rect::from_bounds(min_x - pad, min_y - pad, max_x + pad, max_y + pad)
+    }
+}
+
+// twice the signed area; positive when the path winds clockwise on screen
+fn signed_area(path: &[(f32, f32)]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..path.len() {
+        let (a, b) = (path[i], path[(i + 1) % path.len()]);
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+    area
+}
+
+fn cross(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn contains(triangle: [(f32, f32); 3], p: (f32, f32)) -> bool {
+    let [a, b, c] = triangle;
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+// ear-clipping triangulation of a simple polygon. Self-intersecting polygons
+// have no complete set of ears; whatever is left is fanned
+fn triangulate(path: &[(f32, f32)]) -> Vec<[usize; 3]> {
+    let mut indices: Vec<usize> = (0..path.len()).collect();
+    if signed_area(path) < 0.0 {
+        indices.reverse();
+    }
+    let mut triangles = Vec::with_capacity(path.len() - 2);
+    while indices.len() > 3 {
+        let n = indices.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (indices[(i + n - 1) % n], indices[i], indices[(i + 1) % n]);
+            let triangle = [path[a], path[b], path[c]];
+            cross(path[a], path[b], path[c]) > 0.0
+                && indices
+                    .iter()
+                    .filter(|&&j| j != a && j != b && j != c)
+                    .all(|&j| !contains(triangle, path[j]))
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([indices[(i + n - 1) % n], indices[i], indices[(i + 1) % n]]);
+                indices.remove(i);
+            }
+            None => break,
+        }
+    }
+    for i in 1..indices.len() - 1 {
+        triangles.push([indices[0], indices[i], indices[i + 1]]);
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // total unsigned area of the triangles, which only matches the polygon's
+    // area when they cover it without overlapping
+    fn covered_area(path: &[(f32, f32)], triangles: &[[usize; 3]]) -> f32 {
+        triangles
+            .iter()
+            .map(|&[a, b, c]| cross(path[a], path[b], path[c]).abs() / 2.0)
+            .sum()
+    }
+
+    fn assert_covers(path: &[(f32, f32)]) {
+        let triangles = triangulate(path);
+        assert_eq!(triangles.len(), path.len() - 2);
+        let area = signed_area(path).abs() / 2.0;
+        assert!((covered_area(path, &triangles) - area).abs() < 1e-3);
+    }
+
+    #[test]
+    fn triangulates_concave_polygons() {
+        // an L, in both windings
+        let mut l = vec![
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ];
+        assert_covers(&l);
+        l.reverse();
+        assert_covers(&l);
+        // a five-pointed star, whose first few vertices are all reflex or blocked
+        let star: Vec<(f32, f32)> = (0..10)
+            .map(|i| {
+                let radius = if i % 2 == 0 { 10.0 } else { 4.0 };
+                let angle = i as f32 * PI / 5.0;
+                (radius * angle.cos(), radius * angle.sin())
+            })
+            .collect();
+        assert_covers(&star);
+    }
+
+    #[test]
+    fn triangulates_degenerate_polygons() {
+        // a vertex in the middle of an edge
+        assert_covers(&[(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)]);
+        // a repeated vertex
+        assert_covers(&[(0.0, 0.0), (2.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)]);
+        // every point on one line
+        let line = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0)];
+        let triangles = triangulate(&line);
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(covered_area(&line, &triangles), 0.0);
+    }
+
+    #[test]
+    fn triangulates_self_intersecting_polygons() {
+        // a bow tie has no complete triangulation, but every vertex is still used
+        let bow_tie = [(0.0, 0.0), (2.0, 2.0), (2.0, 0.0), (0.0, 2.0)];
+        let triangles = triangulate(&bow_tie);
+        assert_eq!(triangles.len(), 2);
+        assert!(triangles.iter().flatten().all(|&i| i < bow_tie.len()));
+        for i in 0..bow_tie.len() {
+            assert!(triangles.iter().flatten().any(|&j| j == i));
+        }
+    }
+
+    #[test]
+    fn fills_only_closed_shapes() {
+        let mut line = Shape::new(ShapeKind::Line, (0.0, 0.0));
+        line.set_cursor((4.0, 4.0), false);
+        assert!(line.fill_triangles().is_empty());
+
+        let mut rectangle = Shape::new(ShapeKind::Rectangle, (0.0, 0.0));
+        rectangle.set_cursor((4.0, 3.0), false);
+        let points = rectangle.fill_triangles();
+        assert_eq!(points.len(), 6);
+        let area: f32 = points
+            .chunks(3)
+            .map(|t| cross(t[0], t[1], t[2]).abs() / 2.0)
+            .sum();
+        assert!((area - 12.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,209 @@
+use super::pointer_state::PointerSample;
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+
+// largest moving-average window, reached at full strength
+const MAX_AVERAGE_WINDOW: f32 = 32.0;
+// longest lazy rope in canvas pixels, reached at full strength
+const MAX_ROPE_LENGTH: f32 = 120.0;
+// largest distance between curve control points in canvas pixels, reached at full strength
+const MAX_CONTROL_DISTANCE: f32 = 40.0;
+// distance between points generated along a fitted curve
+const CURVE_STEP: f32 = 2.0;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StabilizerMode {
+    None,
+    MovingAverage,
+    LazyRope,
+    CatmullRom,
+}
+
+/// Smooths raw pointer samples before they are turned into dabs.
+pub struct Stabilizer {
+    mode: StabilizerMode,
+    strength: f32,
+    window: VecDeque<PointerSample>,
+    last_raw: Option<PointerSample>,
+    last_output: Option<PointerSample>,
+}
+
+impl Stabilizer {
+    pub fn new(mode: StabilizerMode, strength: f32) -> Self {
+        Self {
+            mode,
+            strength: strength.clamp(0.0, 1.0),
+            window: VecDeque::new(),
+            last_raw: None,
+            last_output: None,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: StabilizerMode, strength: f32) {
+        *self = Self::new(mode, strength);
+    }
+
+    /// Clears all state from the previous stroke.
+    pub fn begin(&mut self) {
+        self.window.clear();
+        self.last_raw = None;
+        self.last_output = None;
+    }
+
+    /// Feeds one raw sample and returns the smoothed samples that are now known.
+    pub fn push(&mut self, sample: PointerSample) -> Vec<PointerSample> {
+        self.last_raw = Some(sample);
+        let output = match self.mode {
+            StabilizerMode::None => vec![sample],
+            StabilizerMode::MovingAverage => self.push_average(sample),
+            StabilizerMode::LazyRope => self.push_rope(sample),
+            StabilizerMode::CatmullRom => self.push_curve(sample),
+        };
+        if let Some(last) = output.last() {
+            self.last_output = Some(*last);
+        }
+        output
+    }
+
+    /// Ends the stroke, returning any samples still held back by the smoothing.
+    pub fn finish(&mut self) -> Vec<PointerSample> {
+        let last_raw = match self.last_raw {
+            Some(sample) => sample,
+            None => return Vec::new(),
+        };
+        let output = match self.mode {
+            // the rope intentionally leaves the tail of the stroke behind
+            StabilizerMode::None | StabilizerMode::LazyRope => Vec::new(),
+            // let the averaged line catch up with the pointer
+            StabilizerMode::MovingAverage => match self.last_output {
+                Some(last) if last.distance(&last_raw) > 0.0 => vec![last_raw],
+                _ => Vec::new(),
+            },
+            StabilizerMode::CatmullRom => self.finish_curve(last_raw),
+        };
+        self.begin();
+        output
+    }
+
+    fn push_average(&mut self, sample: PointerSample) -> Vec<PointerSample> {
+        let size = 1 + (self.strength * (MAX_AVERAGE_WINDOW - 1.0)).round() as usize;
+        self.window.push_back(sample);
+        while self.window.len() > size {
+            self.window.pop_front();
+        }
+        let count = self.window.len() as f32;
+        let mut average = PointerSample {
+            x: 0.0,
+            y: 0.0,
+            pressure: 0.0,
+            ..sample
+        };
+        for s in &self.window {
+            average.x += s.x / count;
+            average.y += s.y / count;
+            average.pressure += s.pressure / count;
+        }
+        vec![average]
+    }
+
+    fn push_rope(&mut self, sample: PointerSample) -> Vec<PointerSample> {
+        let anchor = match self.last_output {
+            Some(anchor) => anchor,
+            None => return vec![sample],
+        };
+        let rope = self.strength * MAX_ROPE_LENGTH;
+        let distance = anchor.distance(&sample);
+        if distance <= rope {
+            return Vec::new();
+        }
+        // drag the anchor along the rope, keeping the pointer's pen attributes
+        let t = (distance - rope) / distance;
+        let mut pulled = sample;
+        pulled.x = anchor.x + (sample.x - anchor.x) * t;
+        pulled.y = anchor.y + (sample.y - anchor.y) * t;
+        vec![pulled]
+    }
+
+    fn push_curve(&mut self, sample: PointerSample) -> Vec<PointerSample> {
+        let min_distance = self.strength * MAX_CONTROL_DISTANCE;
+        match self.window.back() {
+            None => {
+                // the first point doubles as its own predecessor
+                self.window.push_back(sample);
+                self.window.push_back(sample);
+                return vec![sample];
+            }
+            Some(last) if last.distance(&sample) < min_distance.max(f32::EPSILON) => {
+                return Vec::new()
+            }
+            _ => {}
+        }
+        self.window.push_back(sample);
+        self.curve_segments(false)
+    }
+
+    fn finish_curve(&mut self, last_raw: PointerSample) -> Vec<PointerSample> {
+        if let Some(last) = self.window.back() {
+            if last.distance(&last_raw) > f32::EPSILON {
+                self.window.push_back(last_raw);
+            }
+        }
+        let mut output = self.curve_segments(false);
+        output.extend(self.curve_segments(true));
+        output
+    }
+
+    // Emits the segment between the 2nd and 3rd control points once the 4th is
+    // known; on `finish` the final segment reuses the endpoint as its successor.
+    fn curve_segments(&mut self, finish: bool) -> Vec<PointerSample> {
+        let w = &self.window;
+        let (p0, p1, p2, p3) = match (w.len(), finish) {
+            (4, false) => (w[0], w[1], w[2], w[3]),
+            (3, true) => (w[0], w[1], w[2], w[2]),
+            _ => return Vec::new(),
+        };
+        if w.len() == 4 {
+            self.window.pop_front();
+        }
+
+        let length = p1.distance(&p2);
+        let steps = (length / CURVE_STEP).ceil().max(1.0) as usize;
+        (1..=steps)
+            .map(|i| catmull_rom(&p0, &p1, &p2, &p3, i as f32 / steps as f32))
+            .collect()
+    }
+}
+
+// Centripetal Catmull-Rom between p1 and p2, evaluated with the Barry-Goldman pyramid.
+fn catmull_rom(
+    p0: &PointerSample,
+    p1: &PointerSample,
+    p2: &PointerSample,
+    p3: &PointerSample,
+    t: f32,
+) -> PointerSample {
+    let knot = |a: &PointerSample, b: &PointerSample| a.distance(b).sqrt().max(1e-4);
+    let t0 = 0.0;
+    let t1 = t0 + knot(p0, p1);
+    let t2 = t1 + knot(p1, p2);
+    let t3 = t2 + knot(p2, p3);
+    let t = t1 + (t2 - t1) * t;
+
+    let mix = |a: (f32, f32), b: (f32, f32), ta: f32, tb: f32| {
+        let u = (t - ta) / (tb - ta);
+        (a.0 + (b.0 - a.0) * u, a.1 + (b.1 - a.1) * u)
+    };
+    let (q0, q1, q2, q3) = ((p0.x, p0.y), (p1.x, p1.y), (p2.x, p2.y), (p3.x, p3.y));
+    let a1 = mix(q0, q1, t0, t1);
+    let a2 = mix(q1, q2, t1, t2);
+    let a3 = mix(q2, q3, t2, t3);
+    let b1 = mix(a1, a2, t0, t2);
+    let b2 = mix(a2, a3, t1, t3);
+    let (x, y) = mix(b1, b2, t1, t2);
+
+    let mut sample = p1.lerp(p2, (t - t1) / (t2 - t1));
+    sample.x = x;
+    sample.y = y;
+    sample
+}
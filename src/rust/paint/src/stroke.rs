@@ -0,0 +1,84 @@
+use super::pointer_state::PointerSample;
+use super::texture::Rect;
+
+// never place dabs closer than this, regardless of brush size and spacing
+pub const MIN_SPACING: f32 = 0.5;
+
+/// A single brush imprint. Positions and size are in canvas pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct Dab {
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+    pub opacity: f32,
+}
+
+impl Dab {
+    /// Pixels the dab can touch, including a pixel of antialiasing.
+    pub fn bounds(&self) -> Rect {
+        let radius = self.size / 2.0 + 1.0;
+        Rect::from_bounds(
+            self.x - radius,
+            self.y - radius,
+            self.x + radius,
+            self.y + radius,
+        )
+    }
+}
+
+/// The samples of one pointer-down to pointer-up gesture, and the state needed
+/// to keep placing dabs at even intervals along it.
+pub struct Stroke {
+    points: Vec<PointerSample>,
+    spacing: f32,
+    distance_to_next: f32,
+}
+
+impl Stroke {
+    /// Creates an empty stroke. `spacing` is the distance between dabs as a fraction of dab size.
+    pub fn new(spacing: f32) -> Self {
+        Self {
+            points: Vec::new(),
+            spacing,
+            distance_to_next: 0.0,
+        }
+    }
+
+    pub fn points(&self) -> &[PointerSample] {
+        &self.points
+    }
+
+    /// Appends `sample` to the stroke and returns the dabs that fall on the new
+    /// segment. `dab_at` maps an (interpolated) sample to the dab drawn there.
+    pub fn add_point<F>(&mut self, sample: PointerSample, dab_at: F) -> Vec<Dab>
+    where
+        F: Fn(&PointerSample) -> Dab,
+    {
+        let mut dabs = Vec::new();
+        let last = match self.points.last() {
+            Some(last) => *last,
+            None => {
+                let dab = dab_at(&sample);
+                self.distance_to_next = self.step(&dab);
+                self.points.push(sample);
+                dabs.push(dab);
+                return dabs;
+            }
+        };
+        self.points.push(sample);
+
+        let length = last.distance(&sample);
+        let mut distance = self.distance_to_next;
+        while distance <= length {
+            let dab = dab_at(&last.lerp(&sample, distance / length));
+            distance += self.step(&dab);
+            dabs.push(dab);
+        }
+        self.distance_to_next = distance - length;
+        dabs
+    }
+
+    fn step(&self, dab: &Dab) -> f32 {
+        (dab.size * self.spacing).max(MIN_SPACING)
+    }
+}
@@ -0,0 +1,304 @@
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGl2RenderingContext as WGL2, WebGlFramebuffer, WebGlTexture};
+
+/// A pixel rectangle with its origin at the top left of the document.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The smallest rect containing the given bounds, rounded outwards to whole pixels.
+    pub fn from_bounds(min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> Self {
+        let x = min_x.floor() as i32;
+        let y = min_y.floor() as i32;
+        Self::new(x, y, max_x.ceil() as i32 - x, max_y.ceil() as i32 - y)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Rect::new(x, y, (right - x).max(0), (bottom - y).max(0))
+    }
+
+    /// Size of the rect's pixels in bytes when stored as RGBA8.
+    pub fn byte_len(&self) -> usize {
+        (self.width.max(0) * self.height.max(0) * 4) as usize
+    }
+}
+
+/// How an image is positioned when it is drawn into a document.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImagePlacement {
+    // centered at its original size
+    Center,
+    // scaled to fit inside the document, keeping its aspect ratio
+    Fit,
+    // scaled to cover the document, keeping its aspect ratio
+    Fill,
+    // scaled to exactly the document size
+    Stretch,
+}
+
+impl ImagePlacement {
+    /// The destination `(x, y, width, height)` for a `width` x `height` image in the document.
+    pub fn place(&self, width: f32, height: f32, doc_width: f32, doc_height: f32) -> [f32; 4] {
+        let scale = match self {
+            ImagePlacement::Center => 1.0,
+            ImagePlacement::Fit => (doc_width / width).min(doc_height / height),
+            ImagePlacement::Fill => (doc_width / width).max(doc_height / height),
+            ImagePlacement::Stretch => return [0.0, 0.0, doc_width, doc_height],
+        };
+        let (w, h) = (width * scale, height * scale);
+        [(doc_width - w) / 2.0, (doc_height - h) / 2.0, w, h]
+    }
+}
+
+/// Where existing content stays when the document is resized.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// The offset of the old content inside the new size, as a fraction of the
+    /// size difference along each axis.
+    pub fn factors(&self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// The filter used when pixels are scaled.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resampling {
+    Bilinear = 0,
+    Bicubic = 1,
+}
+
+/// An RGBA texture with a framebuffer attached, used for anything the engine draws into.
+pub struct RenderTarget {
+    pub texture: WebGlTexture,
+    pub framebuffer: WebGlFramebuffer,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl RenderTarget {
+    pub fn new(gl: &WGL2, width: i32, height: i32) -> Result<Self, JsValue> {
+        let level = 0;
+        let border = 0;
+        let texture = gl.create_texture().ok_or("Failed to create texture")?;
+        gl.bind_texture(WGL2::TEXTURE_2D, Some(&texture));
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            WGL2::TEXTURE_2D,
+            level,
+            WGL2::RGBA as i32,
+            width,
+            height,
+            border,
+            WGL2::RGBA,
+            WGL2::UNSIGNED_BYTE,
+            None,
+        )?;
+        gl.tex_parameteri(
+            WGL2::TEXTURE_2D,
+            WGL2::TEXTURE_MIN_FILTER,
+            WGL2::LINEAR as i32,
+        );
+        gl.tex_parameteri(
+            WGL2::TEXTURE_2D,
+            WGL2::TEXTURE_WRAP_S,
+            WGL2::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameteri(
+            WGL2::TEXTURE_2D,
+            WGL2::TEXTURE_WRAP_T,
+            WGL2::CLAMP_TO_EDGE as i32,
+        );
+        let framebuffer = gl
+            .create_framebuffer()
+            .ok_or("Failed to create framebuffer")?;
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, Some(&framebuffer));
+        gl.framebuffer_texture_2d(
+            WGL2::FRAMEBUFFER,
+            WGL2::COLOR_ATTACHMENT0,
+            WGL2::TEXTURE_2D,
+            Some(&texture),
+            level,
+        );
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
+        gl.bind_texture(WGL2::TEXTURE_2D, None);
+
+        let target = Self {
+            texture,
+            framebuffer,
+            width,
+            height,
+        };
+        target.clear(gl, 0.0, 0.0, 0.0, 0.0);
+        Ok(target)
+    }
+
+    /// Binds the framebuffer and sets the viewport to cover it.
+    pub fn bind(&self, gl: &WGL2) {
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, Some(&self.framebuffer));
+        gl.viewport(0, 0, self.width, self.height);
+    }
+
+    pub fn clear(&self, gl: &WGL2, r: f32, g: f32, b: f32, a: f32) {
+        self.bind(gl);
+        gl.clear_color(r, g, b, a);
+        gl.clear(WGL2::COLOR_BUFFER_BIT);
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    // GL's origin is at the bottom left; flip a document rect's y to match
+    fn gl_y(&self, rect: &Rect) -> i32 {
+        self.height - rect.y - rect.height
+    }
+
+    /// Reads `rect` back as RGBA8. Rows are in GL order, bottom row first.
+    pub fn read_pixels(&self, gl: &WGL2, rect: &Rect) -> Result<Vec<u8>, JsValue> {
+        let mut pixels = vec![0u8; rect.byte_len()];
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, Some(&self.framebuffer));
+        let result = gl.read_pixels_with_opt_u8_array(
+            rect.x,
+            self.gl_y(rect),
+            rect.width,
+            rect.height,
+            WGL2::RGBA,
+            WGL2::UNSIGNED_BYTE,
+            Some(&mut pixels),
+        );
+        gl.bind_framebuffer(WGL2::FRAMEBUFFER, None);
+        result?;
+        Ok(pixels)
+    }
+
+    /// Replaces `rect` with RGBA8 `pixels` laid out as returned by `read_pixels`.
+    pub fn write_pixels(&self, gl: &WGL2, rect: &Rect, pixels: &[u8]) -> Result<(), JsValue> {
+        gl.bind_texture(WGL2::TEXTURE_2D, Some(&self.texture));
+        let result = gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+            WGL2::TEXTURE_2D,
+            0,
+            rect.x,
+            self.gl_y(rect),
+            rect.width,
+            rect.height,
+            WGL2::RGBA,
+            WGL2::UNSIGNED_BYTE,
+            Some(pixels),
+        );
+        gl.bind_texture(WGL2::TEXTURE_2D, None);
+        result
+    }
+
+    /// Copies all of `source`, which must be the same size, into this target.
+    pub fn copy_from(&self, gl: &WGL2, source: &RenderTarget) {
+        self.copy_rect_from(gl, source, &source.rect(), 0, 0);
+    }
+
+    /// Copies `rect` of `source` so its top left lands at `x, y` in this target.
+    /// Whatever falls outside either target is skipped.
+    pub fn copy_rect_from(&self, gl: &WGL2, source: &RenderTarget, rect: &Rect, x: i32, y: i32) {
+        // clip against both targets, keeping source and destination in step
+        let (dx, dy) = (x - rect.x, y - rect.y);
+        let src = rect.intersect(&source.rect());
+        let dst = Rect::new(src.x + dx, src.y + dy, src.width, src.height).intersect(&self.rect());
+        if dst.is_empty() {
+            return;
+        }
+        let src = Rect::new(dst.x - dx, dst.y - dy, dst.width, dst.height);
+        let (src_y, dst_y) = (source.gl_y(&src), self.gl_y(&dst));
+        gl.bind_framebuffer(WGL2::READ_FRAMEBUFFER, Some(&source.framebuffer));
+        gl.bind_framebuffer(WGL2::DRAW_FRAMEBUFFER, Some(&self.framebuffer));
+        gl.blit_framebuffer(
+            src.x,
+            src_y,
+            src.x + src.width,
+            src_y + src.height,
+            dst.x,
+            dst_y,
+            dst.x + dst.width,
+            dst_y + dst.height,
+            WGL2::COLOR_BUFFER_BIT,
+            WGL2::NEAREST,
+        );
+        gl.bind_framebuffer(WGL2::READ_FRAMEBUFFER, None);
+        gl.bind_framebuffer(WGL2::DRAW_FRAMEBUFFER, None);
+    }
+
+    /// Builds mipmaps so the texture can be drawn scaled down without aliasing.
+    pub fn generate_mipmaps(&self, gl: &WGL2) {
+        gl.bind_texture(WGL2::TEXTURE_2D, Some(&self.texture));
+        gl.generate_mipmap(WGL2::TEXTURE_2D);
+        gl.tex_parameteri(
+            WGL2::TEXTURE_2D,
+            WGL2::TEXTURE_MIN_FILTER,
+            WGL2::LINEAR_MIPMAP_LINEAR as i32,
+        );
+        gl.bind_texture(WGL2::TEXTURE_2D, None);
+    }
+
+    pub fn delete(&self, gl: &WGL2) {
+        gl.delete_framebuffer(Some(&self.framebuffer));
+        gl.delete_texture(Some(&self.texture));
+    }
+}
@@ -0,0 +1,51 @@
+use super::shape::ShapeKind;
+use wasm_bindgen::prelude::*;
+
+/// What a primary-button drag on the canvas does.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tool {
+    Brush,
+    Eraser,
+    Bucket,
+    Line,
+    Rectangle,
+    Ellipse,
+    /// Click to place vertices; click the first or last vertex again to close.
+    Polygon,
+    /// Picks up the color under the pointer as the brush color.
+    Eyedropper,
+    RectangleSelect,
+    EllipseSelect,
+    /// Drags out a freehand selection outline.
+    Lasso,
+    /// Selects the region of similar color around a click.
+    MagicWand,
+    /// Moves, rotates and distorts the selected pixels, or the whole layer.
+    Transform,
+    /// Drags out a gradient across the layer, or the selection.
+    Gradient,
+}
+
+impl Tool {
+    /// The shape the tool draws, if it is a shape tool.
+    pub fn shape(self) -> Option<ShapeKind> {
+        match self {
+            Tool::Line => Some(ShapeKind::Line),
+            Tool::Rectangle => Some(ShapeKind::Rectangle),
+            Tool::Ellipse => Some(ShapeKind::Ellipse),
+            Tool::Polygon => Some(ShapeKind::Polygon),
+            _ => None,
+        }
+    }
+
+    /// The outline the tool selects with, if it is a selection tool.
+    pub fn selection_shape(self) -> Option<ShapeKind> {
+        match self {
+            Tool::RectangleSelect => Some(ShapeKind::Rectangle),
+            Tool::EllipseSelect => Some(ShapeKind::Ellipse),
+            Tool::Lasso => Some(ShapeKind::Polygon),
+            _ => None,
+        }
+    }
+}
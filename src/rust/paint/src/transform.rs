@@ -0,0 +1,229 @@
+use super::selection::Selection;
+use super::texture::{Rect, RenderTarget};
+use super::view::Affine;
+
+/// A projective transform mapping `(x, y)` to `(m0 x + m1 y + m2, m3 x + m4 y + m5)`
+/// divided by `m6 x + m7 y + m8`.
+#[derive(Clone, Copy, Debug)]
+pub struct Homography {
+    m: [f64; 9],
+}
+
+impl Homography {
+    /// Maps a `width` x `height` rect with its origin at the top left onto
+    /// `corners`: top left, top right, bottom right and bottom left.
+    pub fn from_rect(width: f32, height: f32, corners: &[(f32, f32); 4]) -> Self {
+        let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = corners.map(|(x, y)| (x as f64, y as f64));
+        // the unit square onto the quad, after Heckbert
+        let (sx, sy) = (x0 - x1 + x2 - x3, y0 - y1 + y2 - y3);
+        let (dx1, dx2, dy1, dy2) = (x1 - x2, x3 - x2, y1 - y2, y3 - y2);
+        let det = dx1 * dy2 - dx2 * dy1;
+        let (g, h) = if det.abs() < f64::EPSILON {
+            (0.0, 0.0)
+        } else {
+            ((sx * dy2 - dx2 * sy) / det, (dx1 * sy - sx * dy1) / det)
+        };
+        let square = [
+            x1 - x0 + g * x1,
+            x3 - x0 + h * x3,
+            x0,
+            y1 - y0 + g * y1,
+            y3 - y0 + h * y3,
+            y0,
+            g,
+            h,
+            1.0,
+        ];
+        // then the rect onto the unit square
+        let (w, h) = (width.max(1.0) as f64, height.max(1.0) as f64);
+        let mut m = square;
+        for row in 0..3 {
+            m[row * 3] /= w;
+            m[row * 3 + 1] /= h;
+        }
+        Self { m }
+    }
+
+    pub fn inverse(&self) -> Option<Homography> {
+        let m = &self.m;
+        let adjugate = [
+            m[4] * m[8] - m[5] * m[7],
+            m[2] * m[7] - m[1] * m[8],
+            m[1] * m[5] - m[2] * m[4],
+            m[5] * m[6] - m[3] * m[8],
+            m[0] * m[8] - m[2] * m[6],
+            m[2] * m[3] - m[0] * m[5],
+            m[3] * m[7] - m[4] * m[6],
+            m[1] * m[6] - m[0] * m[7],
+            m[0] * m[4] - m[1] * m[3],
+        ];
+        let det = m[0] * adjugate[0] + m[1] * adjugate[3] + m[2] * adjugate[6];
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        Some(Homography {
+            m: adjugate.map(|value| value / det),
+        })
+    }
+
+    /// Maps a point, or returns `None` if it lands behind the horizon.
+    pub fn apply(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let m = &self.m;
+        let (x, y) = (x as f64, y as f64);
+        let w = m[6] * x + m[7] * y + m[8];
+        if w <= 0.0 {
+            return None;
+        }
+        Some((
+            ((m[0] * x + m[1] * y + m[2]) / w) as f32,
+            ((m[3] * x + m[4] * y + m[5]) / w) as f32,
+        ))
+    }
+
+    /// Column-major 3x3 matrix for a `mat3` uniform.
+    pub fn mat3(&self) -> [f32; 9] {
+        let m = &self.m;
+        [m[0], m[3], m[6], m[1], m[4], m[7], m[2], m[5], m[8]].map(|value| value as f32)
+    }
+}
+
+/// What a drag on a floating transform changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Handle {
+    Move,
+    /// Moves one corner freely, for skew and perspective.
+    Corner(usize),
+    /// Rotates around the center, when grabbed outside the pixels.
+    Rotate,
+}
+
+/// Pixels lifted off a layer while they are being transformed.
+pub struct Floating {
+    /// The lifted pixels, premultiplied, in GL row order.
+    pub target: RenderTarget,
+    /// The layer with the lifted pixels cut out, which previews are drawn over.
+    pub base: RenderTarget,
+    /// Where the pixels go: top left, top right, bottom right and bottom left, in document pixels.
+    pub corners: [(f32, f32); 4],
+    /// Where the pixels came from, in document pixels.
+    pub source: Rect,
+    /// The selection the pixels were lifted with, which moves along with them.
+    pub selection: Option<Selection>,
+    /// The active drag and the pointer's last document position.
+    pub drag: Option<(Handle, (f32, f32))>,
+}
+
+impl Floating {
+    pub fn homography(&self) -> Homography {
+        Homography::from_rect(
+            self.target.width as f32,
+            self.target.height as f32,
+            &self.corners,
+        )
+    }
+
+    pub fn center(&self) -> (f32, f32) {
+        let (x, y) = self
+            .corners
+            .iter()
+            .fold((0.0, 0.0), |(x, y), corner| (x + corner.0, y + corner.1));
+        (x / 4.0, y / 4.0)
+    }
+
+    /// Applies `transform` to the corners, taking the center as the origin.
+    pub fn transform(&mut self, transform: &Affine) {
+        let (cx, cy) = self.center();
+        let around = Affine::translate(cx, cy)
+            .then(transform)
+            .then(&Affine::translate(-cx, -cy));
+        for corner in self.corners.iter_mut() {
+            *corner = around.apply(corner.0, corner.1);
+        }
+    }
+
+    /// Document pixels the transformed pixels may cover.
+    pub fn bounds(&self) -> Rect {
+        let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+        let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+        for (x, y) in self.corners.iter() {
+            min_x = min_x.min(*x);
+            min_y = min_y.min(*y);
+            max_x = max_x.max(*x);
+            max_y = max_y.max(*y);
+        }
+        Rect::from_bounds(min_x - 1.0, min_y - 1.0, max_x + 1.0, max_y + 1.0)
+    }
+
+    /// The handle at document point `at`, where corners can be grabbed within `reach` pixels.
+    pub fn handle_at(&self, at: (f32, f32), reach: f32) -> Handle {
+        let corner = self
+            .corners
+            .iter()
+            .position(|(x, y)| (x - at.0).hypot(y - at.1) <= reach);
+        if let Some(index) = corner {
+            return Handle::Corner(index);
+        }
+        let inside = self
+            .homography()
+            .inverse()
+            .and_then(|inverse| inverse.apply(at.0, at.1))
+            .map(|(x, y)| {
+                x >= 0.0
+                    && y >= 0.0
+                    && x <= self.target.width as f32
+                    && y <= self.target.height as f32
+            })
+            .unwrap_or(false);
+        if inside {
+            Handle::Move
+        } else {
+            Handle::Rotate
+        }
+    }
+
+    /// Follows a drag of `handle` from document point `from` to `to`.
+    pub fn drag_handle(&mut self, handle: Handle, from: (f32, f32), to: (f32, f32)) {
+        match handle {
+            Handle::Move => self.transform(&Affine::translate(to.0 - from.0, to.1 - from.1)),
+            Handle::Corner(index) => {
+                let corner = &mut self.corners[index];
+                corner.0 += to.0 - from.0;
+                corner.1 += to.1 - from.1;
+            }
+            Handle::Rotate => {
+                let (cx, cy) = self.center();
+                let before = (from.1 - cy).atan2(from.0 - cx);
+                let after = (to.1 - cy).atan2(to.0 - cx);
+                self.transform(&Affine::rotate(after - before));
+            }
+        }
+    }
+
+    /// The lifted selection carried through the transform, or `None` if the
+    /// pixels weren't lifted with one.
+    pub fn transformed_selection(&self) -> Option<Selection> {
+        let selection = self.selection.as_ref()?;
+        let mut moved = Selection::new(selection.mask.width, selection.mask.height);
+        let inverse = self.homography().inverse()?;
+        let (width, height) = (moved.mask.width, moved.mask.height);
+        for y in 0..height {
+            for x in 0..width {
+                // nearest source pixel of this pixel's center
+                let source = inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+                let (sx, sy) = match source {
+                    Some((sx, sy)) => (sx.floor() as i32, sy.floor() as i32),
+                    None => continue,
+                };
+                if sx < 0 || sy < 0 || sx >= self.source.width || sy >= self.source.height {
+                    continue;
+                }
+                let (px, py) = (self.source.x + sx, self.source.y + sy);
+                moved.mask.coverage[y * width + x] =
+                    selection.mask.coverage[py as usize * width + px as usize];
+            }
+        }
+        moved.active = true;
+        moved.settle();
+        Some(moved)
+    }
+}
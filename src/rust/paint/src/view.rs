@@ -0,0 +1,250 @@
+use std::f32::consts::PI;
+
+pub const MIN_ZOOM: f32 = 0.01;
+pub const MAX_ZOOM: f32 = 64.0;
+
+/// A 2D affine transform mapping `(x, y)` to
+/// `(a * x + c * y + e, b * x + d * y + f)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Affine {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl Affine {
+    pub fn identity() -> Self {
+        Self::scale(1.0, 1.0)
+    }
+
+    pub fn translate(x: f32, y: f32) -> Self {
+        Self {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 1.0,
+            e: x,
+            f: y,
+        }
+    }
+
+    pub fn scale(x: f32, y: f32) -> Self {
+        Self {
+            a: x,
+            b: 0.0,
+            c: 0.0,
+            d: y,
+            e: 0.0,
+            f: 0.0,
+        }
+    }
+
+    pub fn rotate(radians: f32) -> Self {
+        let (sin, cos) = radians.sin_cos();
+        Self {
+            a: cos,
+            b: sin,
+            c: -sin,
+            d: cos,
+            e: 0.0,
+            f: 0.0,
+        }
+    }
+
+    /// Shears by the given angles: x moves with y by `tan(x)`, and y with x by `tan(y)`.
+    pub fn skew(x: f32, y: f32) -> Self {
+        Self {
+            a: 1.0,
+            b: y.tan(),
+            c: x.tan(),
+            d: 1.0,
+            e: 0.0,
+            f: 0.0,
+        }
+    }
+
+    /// The transform that applies `other` first, then `self`.
+    pub fn then(&self, other: &Affine) -> Affine {
+        Affine {
+            a: self.a * other.a + self.c * other.b,
+            b: self.b * other.a + self.d * other.b,
+            c: self.a * other.c + self.c * other.d,
+            d: self.b * other.c + self.d * other.d,
+            e: self.a * other.e + self.c * other.f + self.e,
+            f: self.b * other.e + self.d * other.f + self.f,
+        }
+    }
+
+    pub fn inverse(&self) -> Affine {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() < f32::EPSILON {
+            return Affine::identity();
+        }
+        let (a, b, c, d) = (self.d / det, -self.b / det, -self.c / det, self.a / det);
+        Affine {
+            a,
+            b,
+            c,
+            d,
+            e: -(a * self.e + c * self.f),
+            f: -(b * self.e + d * self.f),
+        }
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.c * y + self.e,
+            self.b * x + self.d * y + self.f,
+        )
+    }
+
+    /// Column-major 3x3 matrix for a `mat3` uniform.
+    pub fn mat3(&self) -> [f32; 9] {
+        [
+            self.a, self.b, 0.0, self.c, self.d, 0.0, self.e, self.f, 1.0,
+        ]
+    }
+}
+
+/// Maps document pixels (top-left origin) to NDC of a `width` x `height` target.
+pub fn pixels_to_ndc(width: f32, height: f32) -> Affine {
+    Affine::translate(-1.0, 1.0).then(&Affine::scale(2.0 / width, -2.0 / height))
+}
+
+/// How the document is placed on the canvas. The document's center sits at the
+/// canvas center offset by `pan`, scaled by `zoom` and rotated clockwise by
+/// `rotation`; the result is optionally mirrored horizontally on screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub zoom: f32,
+    pub pan_x: f32,
+    pub pan_y: f32,
+    // radians
+    pub rotation: f32,
+    pub flip: bool,
+    // whether the view should keep fitting the document as the canvas resizes
+    pub fitted: bool,
+}
+
+impl View {
+    pub fn new() -> Self {
+        Self {
+            zoom: 1.0,
+            pan_x: 0.0,
+            pan_y: 0.0,
+            rotation: 0.0,
+            flip: false,
+            fitted: false,
+        }
+    }
+
+    /// Centers the document and zooms so all of it is visible, letterboxing the
+    /// rest of the canvas. Rotation and flip are kept.
+    pub fn fit(&mut self, document: (f32, f32), canvas: (f32, f32)) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let width = document.0 * cos.abs() + document.1 * sin.abs();
+        let height = document.0 * sin.abs() + document.1 * cos.abs();
+        self.zoom = (canvas.0 / width)
+            .min(canvas.1 / height)
+            .clamp(MIN_ZOOM, MAX_ZOOM);
+        self.pan_x = 0.0;
+        self.pan_y = 0.0;
+        self.fitted = true;
+    }
+
+    /// Maps document pixels to canvas pixels.
+    pub fn document_to_canvas(&self, document: (f32, f32), canvas: (f32, f32)) -> Affine {
+        let flip = if self.flip { -1.0 } else { 1.0 };
+        Affine::translate(canvas.0 / 2.0 + self.pan_x, canvas.1 / 2.0 + self.pan_y)
+            .then(&Affine::scale(flip, 1.0))
+            .then(&Affine::rotate(self.rotation))
+            .then(&Affine::scale(self.zoom, self.zoom))
+            .then(&Affine::translate(-document.0 / 2.0, -document.1 / 2.0))
+    }
+
+    /// Maps canvas pixels back to document pixels, e.g. for pointer input.
+    pub fn canvas_to_document(&self, document: (f32, f32), canvas: (f32, f32)) -> Affine {
+        self.document_to_canvas(document, canvas).inverse()
+    }
+
+    /// Maps the document quad's NDC to canvas NDC, for the quad vertex shader.
+    pub fn ndc_transform(&self, document: (f32, f32), canvas: (f32, f32)) -> Affine {
+        pixels_to_ndc(canvas.0, canvas.1)
+            .then(&self.document_to_canvas(document, canvas))
+            .then(&pixels_to_ndc(document.0, document.1).inverse())
+    }
+
+    // adjusts the pan so `point` in the document lands on `target` on the canvas
+    fn pin(
+        &mut self,
+        point: (f32, f32),
+        target: (f32, f32),
+        document: (f32, f32),
+        canvas: (f32, f32),
+    ) {
+        let (x, y) = self
+            .document_to_canvas(document, canvas)
+            .apply(point.0, point.1);
+        self.pan_x += target.0 - x;
+        self.pan_y += target.1 - y;
+    }
+
+    /// Sets the zoom, keeping the document point under canvas pixel `at` in place.
+    pub fn zoom_at(&mut self, zoom: f32, at: (f32, f32), document: (f32, f32), canvas: (f32, f32)) {
+        let point = self.canvas_to_document(document, canvas).apply(at.0, at.1);
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.fitted = false;
+        self.pin(point, at, document, canvas);
+    }
+
+    /// Sets the rotation in radians, keeping the document point under canvas pixel `at` in place.
+    pub fn rotate_at(
+        &mut self,
+        radians: f32,
+        at: (f32, f32),
+        document: (f32, f32),
+        canvas: (f32, f32),
+    ) {
+        let point = self.canvas_to_document(document, canvas).apply(at.0, at.1);
+        self.rotation = radians.rem_euclid(2.0 * PI);
+        self.fitted = false;
+        self.pin(point, at, document, canvas);
+    }
+
+    /// Mirrors the view horizontally around canvas pixel `at`.
+    pub fn flip_at(
+        &mut self,
+        flip: bool,
+        at: (f32, f32),
+        document: (f32, f32),
+        canvas: (f32, f32),
+    ) {
+        let point = self.canvas_to_document(document, canvas).apply(at.0, at.1);
+        self.flip = flip;
+        self.pin(point, at, document, canvas);
+    }
+
+    pub fn pan_by(&mut self, dx: f32, dy: f32) {
+        self.pan_x += dx;
+        self.pan_y += dy;
+        self.fitted = false;
+    }
+}
+
+/// What a view drag gesture is doing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewGesture {
+    Pan,
+    Rotate,
+}
+
+/// An in-progress drag that moves the view instead of painting.
+pub struct ViewDrag {
+    pub pointer_id: i32,
+    pub gesture: ViewGesture,
+    pub last_x: f32,
+    pub last_y: f32,
+}